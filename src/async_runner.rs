@@ -0,0 +1,381 @@
+use std::collections::{HashMap, VecDeque};
+use std::io::{self, Read};
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{channel, Receiver};
+use std::sync::Arc;
+
+use tokio::io::{AsyncBufReadExt, BufReader};
+use tokio::process::Command;
+use tokio::sync::mpsc::UnboundedSender;
+use tokio::sync::Semaphore;
+use tokio::task::{self, JoinError, JoinSet};
+
+use outparse::{parse_log_streaming_encoded, BuildReport, Encoding, ParseEvent};
+
+use crate::config::Config;
+use crate::events::{EventKind, PassReason, RunnerEvent};
+use crate::jobs::{tool_succeeded, Job, JobStatus, NextStep, Prerequisites};
+
+/// Asynchronous counterpart of [`Runner`](crate::runner::Runner).
+///
+/// Each pass of a job is a future driven by `tokio::process`, so
+/// builds do not need a thread of their own. Dropping a build future
+/// kills the engine or tool it is waiting on. The number of builds
+/// running at the same time is limited by `max_jobs`.
+#[derive(Clone)]
+pub struct AsyncRunner {
+    config: Arc<Config>,
+    slots: Arc<Semaphore>,
+    events: Option<UnboundedSender<RunnerEvent>>,
+}
+
+impl AsyncRunner {
+    pub fn new(config: Arc<Config>) -> AsyncRunner {
+        let slots = Arc::new(Semaphore::new(config.max_jobs.max(1)));
+        AsyncRunner {
+            config,
+            slots,
+            events: None,
+        }
+    }
+
+    /// Send the events of every job built by this runner to `sender`.
+    pub fn with_events(mut self, sender: UnboundedSender<RunnerEvent>) -> AsyncRunner {
+        self.events = Some(sender);
+        self
+    }
+
+    fn emit(&self, event: RunnerEvent) {
+        if let Some(ref sender) = self.events {
+            let _ = sender.send(event);
+        }
+    }
+
+    fn queue(&self, id: usize, path: &Path) -> Job {
+        let job = Job::new(id, self.config.clone(), path);
+        self.emit(RunnerEvent::new(
+            id,
+            EventKind::JobQueued {
+                path: job.path.clone(),
+            },
+        ));
+        job
+    }
+
+    fn complete(&self, job: &Job) {
+        self.emit(RunnerEvent::new(
+            job.id,
+            EventKind::JobFinished {
+                status: job.status.clone(),
+            },
+        ));
+    }
+
+    /// Build a single document, running as many passes and tools
+    /// as it needs, and return the completed job.
+    pub async fn build(&self, id: usize, path: &Path) -> Job {
+        let job = self.queue(id, path);
+        self.run(job).await
+    }
+
+    async fn run(&self, mut job: Job) -> Job {
+        let id = job.id;
+        let _slot = self.slots.acquire().await;
+
+        let mut step = NextStep::Pass(PassReason::Initial);
+        loop {
+            step = match step {
+                NextStep::Pass(reason) => {
+                    self.emit(job.begin_pass(reason));
+                    let mut command = Command::from(job.engine_command());
+                    command.kill_on_drop(true);
+                    let progress = &mut job.progress;
+                    let result = run_pass(command, self.config.log_encoding, |event| {
+                        if let ParseEvent::Message(message) = event {
+                            self.emit(RunnerEvent::new(id, EventKind::MessageParsed(message)));
+                        } else if progress.update(&event) {
+                            self.emit(RunnerEvent::new(id, EventKind::Progress(progress.clone())));
+                        }
+                    })
+                    .await;
+                    match result {
+                        Ok((success, report)) => job.after_pass(success, report),
+                        Err(_) => {
+                            job.status = JobStatus::Failed;
+                            NextStep::Done
+                        }
+                    }
+                }
+                NextStep::Tool(tool) => {
+                    self.emit(RunnerEvent::new(id, EventKind::ToolStarted { tool }));
+                    let mut command = Command::from(job.tool_command(tool));
+                    command.kill_on_drop(true);
+                    let success = match command.status().await {
+                        Ok(status) => tool_succeeded(tool, status),
+                        Err(_) => false,
+                    };
+                    self.emit(RunnerEvent::new(
+                        id,
+                        EventKind::ToolFinished { tool, success },
+                    ));
+                    job.after_tool(tool, success)
+                }
+                NextStep::Done => break,
+            };
+        }
+
+        self.complete(&job);
+        job
+    }
+
+    /// Build all the documents concurrently, returning the result of
+    /// each job in the order the paths were given.
+    ///
+    /// As with the synchronous runner, a document is only built once
+    /// the documents it depends on have been, and fails if any of them
+    /// fail. A job whose task panicked is returned as its `JoinError`.
+    ///
+    /// Must be called from within a tokio runtime.
+    pub async fn build_all<P: AsRef<Path>>(&self, paths: &[P]) -> Vec<Result<Job, JoinError>> {
+        let mut pending: VecDeque<Job> = paths
+            .iter()
+            .enumerate()
+            .map(|(id, path)| self.queue(id, path.as_ref()))
+            .collect();
+        let documents: Vec<(PathBuf, PathBuf)> = pending
+            .iter()
+            .map(|j| (j.key.clone(), j.output_key.clone()))
+            .collect();
+        let documents: Vec<(&PathBuf, &PathBuf)> = documents.iter().map(|(k, o)| (k, o)).collect();
+        for job in pending.iter_mut() {
+            job.resolve_dependencies(&documents);
+        }
+
+        let mut tasks = JoinSet::new();
+        let mut running: HashMap<task::Id, (usize, PathBuf)> = HashMap::new();
+        let mut failed: Vec<PathBuf> = Vec::new();
+        let mut results = Vec::with_capacity(paths.len());
+        loop {
+            let mut i = 0;
+            while i < pending.len() {
+                let state = pending[i].prerequisites(
+                    |name| {
+                        pending.iter().any(|j| &j.key == name)
+                            || running.values().any(|(_, key)| key == name)
+                    },
+                    |name| failed.contains(name),
+                );
+                match state {
+                    Prerequisites::Ready => {
+                        let job = pending.remove(i).unwrap();
+                        let entry = (job.id, job.key.clone());
+                        let runner = self.clone();
+                        let handle = tasks.spawn(async move { runner.run(job).await });
+                        running.insert(handle.id(), entry);
+                    }
+                    Prerequisites::Waiting => i += 1,
+                    Prerequisites::Failed => {
+                        let mut job = pending.remove(i).unwrap();
+                        job.status = JobStatus::Failed;
+                        self.complete(&job);
+                        failed.push(job.key.clone());
+                        results.push((job.id, Ok(job)));
+                    }
+                }
+            }
+
+            // Nothing is running, so whatever is left depends on itself.
+            if tasks.is_empty() {
+                for mut job in pending.drain(..) {
+                    job.status = JobStatus::Failed;
+                    self.complete(&job);
+                    results.push((job.id, Ok(job)));
+                }
+                break;
+            }
+
+            match tasks.join_next_with_id().await {
+                Some(Ok((task, job))) => {
+                    running.remove(&task);
+                    if let JobStatus::Failed = job.status {
+                        failed.push(job.key.clone());
+                    }
+                    results.push((job.id, Ok(job)));
+                }
+                Some(Err(error)) => {
+                    if let Some((id, key)) = running.remove(&error.id()) {
+                        failed.push(key);
+                        results.push((id, Err(error)));
+                    }
+                }
+                None => break,
+            }
+        }
+
+        results.sort_by_key(|(id, _)| *id);
+        results.into_iter().map(|(_, result)| result).collect()
+    }
+}
+
+/// Lines of output read by the runner, handed to the log parser
+/// running on a blocking thread.
+struct LineReader {
+    lines: Receiver<Vec<u8>>,
+    line: Vec<u8>,
+    pos: usize,
+}
+
+impl Read for LineReader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        while self.pos == self.line.len() {
+            match self.lines.recv() {
+                Ok(line) => {
+                    self.line = line;
+                    self.pos = 0;
+                }
+                Err(_) => return Ok(0),
+            }
+        }
+        let n = buf.len().min(self.line.len() - self.pos);
+        buf[..n].copy_from_slice(&self.line[self.pos..self.pos + n]);
+        self.pos += n;
+        Ok(n)
+    }
+}
+
+/// Run a single pass of the engine, parsing its output line by line
+/// as it is written and passing each parse event to `observer`.
+async fn run_pass<F: FnMut(ParseEvent)>(
+    mut command: Command,
+    encoding: Encoding,
+    mut observer: F,
+) -> io::Result<(bool, BuildReport)> {
+    let mut child = command.spawn()?;
+    let stdout = match child.stdout.take() {
+        Some(stdout) => stdout,
+        None => {
+            return Err(io::Error::other(
+                "Could not capture the output of the engine",
+            ))
+        }
+    };
+
+    let (lines, receiver) = channel();
+    let (sender, events) = channel();
+    let reader = LineReader {
+        lines: receiver,
+        line: Vec::new(),
+        pos: 0,
+    };
+    let parser = task::spawn_blocking(move || {
+        parse_log_streaming_encoded(reader, encoding, |event| {
+            let _ = sender.send(event);
+        })
+    });
+
+    let mut stdout = BufReader::new(stdout);
+    loop {
+        let mut line = Vec::new();
+        if stdout.read_until(b'\n', &mut line).await? == 0 {
+            break;
+        }
+        let _ = lines.send(line);
+        events.try_iter().for_each(&mut observer);
+    }
+    drop(lines);
+
+    let report = parser.await.map_err(io::Error::other)?;
+    events.try_iter().for_each(&mut observer);
+    let status = child.wait().await?;
+    Ok((status.success(), report))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::ffi::OsString;
+
+    #[test]
+    fn test_build_all_completes_every_job() {
+        let config = Arc::new(Config {
+            engine: OsString::from("echo"),
+            max_jobs: 2,
+            ..Config::default()
+        });
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()
+            .unwrap();
+
+        let runner = AsyncRunner::new(config);
+        let jobs = runtime.block_on(runner.build_all(&["a.tex", "b.tex", "c.tex"]));
+
+        assert_eq!(jobs.len(), 3);
+        assert!(jobs.iter().all(|j| match j {
+            Ok(job) => matches!(job.status, JobStatus::Success) && job.run_count == 1,
+            Err(_) => false,
+        }));
+    }
+
+    #[test]
+    fn test_build_all_waits_for_prerequisites() {
+        let config = Arc::new(Config {
+            engine: OsString::from("echo"),
+            max_jobs: 2,
+            dependencies: vec![String::from("main.tex:figures.tex")],
+            ..Config::default()
+        });
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()
+            .unwrap();
+        let (sender, mut receiver) = tokio::sync::mpsc::unbounded_channel();
+
+        let runner = AsyncRunner::new(config).with_events(sender);
+        let jobs = runtime.block_on(runner.build_all(&["main.tex", "figures.tex"]));
+
+        let mut finished = Vec::new();
+        while let Ok(event) = receiver.try_recv() {
+            if let EventKind::JobFinished { .. } = event.kind {
+                finished.push(event.job);
+            }
+        }
+        assert_eq!(finished, vec![1, 0]);
+        assert!(jobs.iter().all(|j| matches!(j, Ok(job) if matches!(job.status, JobStatus::Success))));
+    }
+
+    #[test]
+    fn test_messages_streamed_from_output() {
+        let dir = std::env::temp_dir().join(format!("rustex-async-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        let script = "printf '(./a.tex [1]\\nLaTeX Warning: Something odd on input line 9.\\n)\\n'";
+        let config = Arc::new(Config {
+            engine: OsString::from("sh"),
+            flags: vec![OsString::from("-c"), OsString::from(script), OsString::from("sh")],
+            build_directory: Some(dir.clone().into_os_string()),
+            ..Config::default()
+        });
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()
+            .unwrap();
+        let (sender, mut receiver) = tokio::sync::mpsc::unbounded_channel();
+
+        let runner = AsyncRunner::new(config).with_events(sender);
+        runtime.block_on(runner.build(0, Path::new("a.tex")));
+
+        let mut messages = 0;
+        let mut pages = Vec::new();
+        while let Ok(event) = receiver.try_recv() {
+            match event.kind {
+                EventKind::MessageParsed(_) => messages += 1,
+                EventKind::Progress(progress) => pages.extend(progress.page),
+                _ => {}
+            }
+        }
+        assert_eq!(messages, 1);
+        assert_eq!(pages.last(), Some(&1));
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,241 @@
+use std::collections::BTreeSet;
+use std::ffi::OsStr;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+use failure::{err_msg, Error};
+
+use crate::dependencies::{normalise, read_recorder_file};
+
+/// How much of the output of a build to remove.
+///
+/// Each level removes everything the previous one does.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Default)]
+pub enum CleanLevel {
+    /// Auxiliary files that are only used between passes.
+    #[default]
+    Auxiliary,
+    /// Everything except the final output of the engine.
+    KeepOutput,
+    /// Everything generated by the build, including the output.
+    Full,
+}
+
+impl FromStr for CleanLevel {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<CleanLevel, Error> {
+        match s {
+            "aux" => Ok(CleanLevel::Auxiliary),
+            "keep-output" => Ok(CleanLevel::KeepOutput),
+            "full" => Ok(CleanLevel::Full),
+            _ => Err(err_msg(format!(
+                "Unrecognised clean level: {} (expected aux, keep-output or full)",
+                s
+            ))),
+        }
+    }
+}
+
+impl fmt::Display for CleanLevel {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            CleanLevel::Auxiliary => write!(f, "aux"),
+            CleanLevel::KeepOutput => write!(f, "keep-output"),
+            CleanLevel::Full => write!(f, "full"),
+        }
+    }
+}
+
+/// Extensions of the files generated for a job, with the level
+/// from which they are removed.
+const GENERATED: &[(&str, CleanLevel)] = &[
+    // LaTeX engine
+    ("aux", CleanLevel::Auxiliary),
+    ("toc", CleanLevel::Auxiliary),
+    ("lof", CleanLevel::Auxiliary),
+    ("lot", CleanLevel::Auxiliary),
+    ("out", CleanLevel::Auxiliary),
+    ("nav", CleanLevel::Auxiliary),
+    ("snm", CleanLevel::Auxiliary),
+    ("vrb", CleanLevel::Auxiliary),
+    // The recorder file says which other files belong to the job,
+    // so it is kept until everything else has been removed.
+    ("fls", CleanLevel::Full),
+    ("log", CleanLevel::KeepOutput),
+    ("synctex", CleanLevel::KeepOutput),
+    ("synctex.gz", CleanLevel::KeepOutput),
+    // BibTeX and Biber
+    ("bbl", CleanLevel::Auxiliary),
+    ("blg", CleanLevel::Auxiliary),
+    ("bcf", CleanLevel::Auxiliary),
+    ("run.xml", CleanLevel::Auxiliary),
+    // Makeindex
+    ("idx", CleanLevel::Auxiliary),
+    ("ind", CleanLevel::Auxiliary),
+    ("ilg", CleanLevel::Auxiliary),
+    // Final output
+    ("pdf", CleanLevel::Full),
+    ("dvi", CleanLevel::Full),
+    ("xdv", CleanLevel::Full),
+    ("ps", CleanLevel::Full),
+];
+
+/// Extensions of files that are never removed, even if the recorder
+/// shows that the build wrote them.
+const SOURCES: &[&str] = &["tex", "ltx", "bib", "sty", "cls"];
+
+/// Level from which a generated file is removed, found from the
+/// longest known extension the file name ends with.
+fn level_of(name: &str) -> Option<CleanLevel> {
+    GENERATED
+        .iter()
+        .filter(|(ext, _)| name.ends_with(&format!(".{}", ext)))
+        .max_by_key(|(ext, _)| ext.len())
+        .map(|&(_, level)| level)
+}
+
+fn is_source(path: &Path) -> bool {
+    match path.extension().and_then(OsStr::to_str) {
+        Some(ext) => SOURCES.contains(&ext),
+        None => false,
+    }
+}
+
+/// Files generated by building `jobname` in `dir` that are removed
+/// at `level`, in sorted order.
+///
+/// Files are found from the known extensions of each tool, and from
+/// the outputs recorded in `<jobname>.fls` if there is one. Source
+/// files and recorded outputs outside `dir` are never included.
+pub fn generated_files(dir: &Path, jobname: &OsStr, level: CleanLevel) -> Vec<PathBuf> {
+    let mut files = BTreeSet::new();
+    let name = jobname.to_string_lossy();
+    let root = fs::canonicalize(dir).unwrap_or_else(|_| normalise(dir));
+
+    for (ext, _) in GENERATED.iter().filter(|(_, l)| *l <= level) {
+        let path = dir.join(format!("{}.{}", name, ext));
+        if path.is_file() {
+            files.insert(path);
+        }
+    }
+
+    let fls = dir.join(format!("{}.fls", name));
+    for path in read_recorder_file(dir, &fls).outputs {
+        if is_source(&path) || !path.is_file() || !normalise(&path).starts_with(&root) {
+            continue;
+        }
+        let file_name = match path.file_name() {
+            Some(f) => f.to_string_lossy().into_owned(),
+            None => continue,
+        };
+        // Files that are not known to any tool are auxiliary files of
+        // some package, so only need to be kept at the lowest level,
+        // unless they are not named after the job and may be used by
+        // other documents.
+        let unknown = if file_name.starts_with(name.as_ref()) {
+            CleanLevel::Auxiliary
+        } else {
+            CleanLevel::Full
+        };
+        if level_of(&file_name).unwrap_or(unknown) <= level {
+            files.insert(path);
+        }
+    }
+
+    files.into_iter().collect()
+}
+
+/// Remove the files generated by building `jobname` in `dir` at
+/// `level`, returning the files that were (or, in a dry run, would
+/// have been) removed.
+pub fn clean(
+    dir: &Path,
+    jobname: &OsStr,
+    level: CleanLevel,
+    dry_run: bool,
+) -> Result<Vec<PathBuf>, Error> {
+    let files = generated_files(dir, jobname, level);
+    if !dry_run {
+        for f in &files {
+            match fs::remove_file(f) {
+                Err(ref e) if e.kind() != io::ErrorKind::NotFound => {
+                    return Err(err_msg(format!("Could not remove {}: {}", f.display(), e)))
+                }
+                _ => {}
+            }
+        }
+    }
+    Ok(files)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn make_build_dir(name: &str, files: &[&str]) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("rustex-clean-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        for f in files {
+            fs::write(dir.join(f), "").unwrap();
+        }
+        dir
+    }
+
+    fn file_names(files: &[PathBuf]) -> Vec<String> {
+        files
+            .iter()
+            .map(|f| f.file_name().unwrap().to_string_lossy().into_owned())
+            .collect()
+    }
+
+    #[test]
+    fn test_levels_select_known_extensions() {
+        let dir = make_build_dir(
+            "levels",
+            &["main.tex", "main.aux", "main.log", "main.synctex.gz", "main.pdf", "main", "main-figures.tex"],
+        );
+        let jobname = OsStr::new("main");
+
+        let aux = generated_files(&dir, jobname, CleanLevel::Auxiliary);
+        let keep = generated_files(&dir, jobname, CleanLevel::KeepOutput);
+        let full = generated_files(&dir, jobname, CleanLevel::Full);
+
+        assert_eq!(file_names(&aux), vec!["main.aux"]);
+        assert_eq!(file_names(&keep), vec!["main.aux", "main.log", "main.synctex.gz"]);
+        assert_eq!(
+            file_names(&full),
+            vec!["main.aux", "main.log", "main.pdf", "main.synctex.gz"]
+        );
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_recorded_outputs_are_owned() {
+        let dir = make_build_dir("recorder", &["main.tex", "main.thm", "data.tex", "other.aux", "shared.csv"]);
+        let outside = dir.with_extension("dat");
+        fs::write(&outside, "").unwrap();
+        fs::write(
+            dir.join("main.fls"),
+            format!(
+                "PWD {}\nINPUT main.tex\nOUTPUT main.thm\nOUTPUT data.tex\nOUTPUT shared.csv\nOUTPUT ../{}\n",
+                dir.display(),
+                outside.file_name().unwrap().to_string_lossy()
+            ),
+        )
+        .unwrap();
+
+        let aux = clean(&dir, OsStr::new("main"), CleanLevel::Auxiliary, true).unwrap();
+        let full = clean(&dir, OsStr::new("main"), CleanLevel::Full, false).unwrap();
+
+        assert_eq!(file_names(&aux), vec!["main.thm"]);
+        assert_eq!(file_names(&full), vec!["main.fls", "main.thm", "shared.csv"]);
+        assert!(outside.exists());
+        fs::remove_dir_all(&dir).unwrap();
+        fs::remove_file(&outside).unwrap();
+    }
+}
@@ -1,20 +1,27 @@
-use std::ffi::OsString;
+use std::ffi::{OsStr, OsString};
+use std::fs::{self, File};
 use std::path::{Path, PathBuf};
-use std::process::{Command, Stdio};
-use std::slice::Iter;
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::Arc;
-use std::fmt::Display;
+use std::thread;
+use std::time::{Duration, SystemTime};
 
-use failure::{err_msg, Error as E};
-use indicatif::ProgressBar;
+use failure::{bail, Error as E};
+use indicatif::{ProgressBar, ProgressStyle};
 use structopt::StructOpt;
 
-use outparse::{BuildReport, Message};
+use outparse::{
+    explain, format_report, parse_log_encoded, BuildReport, Encoding, ReportFormat, CATALOGUE,
+};
 
-use crate::config::Config;
-use crate::jobs::Job;
-use crate::report::RunnerReport;
-use crate::runner::{Runner};
+use crate::clean::{clean, CleanLevel};
+use crate::config::{BadboxOptions, Config, LockOptions, SuppressOptions};
+use crate::dependencies::{read_recorder_file, source_files};
+use crate::events::EventKind;
+use crate::runner::Runner;
+use crate::suppress::write_baseline;
+use crate::report::annotate;
+use crate::xref::analyse;
 
 
 /// LaTeX file build utility.
@@ -29,7 +36,51 @@ use crate::runner::{Runner};
 /// jobs are executed asyncronously, by making non-blocking calls
 /// to the underlying LaTeX engine.
 #[derive(StructOpt)]
-pub struct CliOptions {
+pub enum CliOptions {
+    /// Build LaTeX documents.
+    #[structopt(name = "build")]
+    Build(BuildOptions),
+
+    /// Remove the files generated by building documents.
+    #[structopt(name = "clean")]
+    Clean(CleanOptions),
+
+    /// Build documents, and build them again whenever
+    /// one of their sources changes.
+    #[structopt(name = "watch")]
+    Watch(WatchOptions),
+
+    /// Print the report for existing log files, without
+    /// building anything.
+    ///
+    /// When documents are given rather than logs, undefined
+    /// references and citations are also checked against their
+    /// sources and bibliographies.
+    #[structopt(name = "report")]
+    Report(ReportOptions),
+
+    /// Check existing log files against limits on the
+    /// number of errors and warnings.
+    ///
+    /// Exits with a non-zero status if any limit is exceeded.
+    #[structopt(name = "check")]
+    Check(CheckOptions),
+
+    /// Print the input files of documents.
+    #[structopt(name = "deps")]
+    Deps(DepsOptions),
+
+    /// Explain an error, given the code shown with it in
+    /// reports.
+    ///
+    /// Lists the codes of all the errors that can be explained
+    /// when no code is given.
+    #[structopt(name = "explain")]
+    Explain(ExplainOptions),
+}
+
+#[derive(StructOpt, Default)]
+pub struct BuildOptions {
     #[structopt(flatten)]
     pub config: Config,
 
@@ -59,28 +110,439 @@ pub struct CliOptions {
     pub files: Vec<PathBuf>,
 }
 
-impl Default for CliOptions {
-    fn default() -> CliOptions {
-        CliOptions {
-            config: Config::default(),
-            files: Vec::new(),
+#[derive(StructOpt)]
+pub struct CleanOptions {
+    /// Directory in which the build occurred.
+    #[structopt(long = "build-dir", parse(from_os_str))]
+    pub build_directory: Option<OsString>,
+
+    /// Which files to remove.
+    ///
+    /// One of "aux" (auxiliary files only), "keep-output"
+    /// (everything except the output of LaTeX) or "full".
+    #[structopt(long = "level", default_value = "aux")]
+    pub level: CleanLevel,
+
+    /// List the files that would be removed, without
+    /// removing them.
+    #[structopt(long = "dry-run")]
+    pub dry_run: bool,
+
+    /// Documents whose generated files should be removed.
+    #[structopt(name = "files", parse(from_os_str))]
+    pub files: Vec<PathBuf>,
+}
+
+#[derive(StructOpt)]
+pub struct WatchOptions {
+    #[structopt(flatten)]
+    pub build: BuildOptions,
+
+    /// Time between checks for changes, in milliseconds.
+    #[structopt(long = "interval", default_value = "500")]
+    pub interval: u64,
+}
+
+#[derive(StructOpt)]
+pub struct ReportOptions {
+    /// Directory in which the build occurred.
+    #[structopt(long = "build-dir", parse(from_os_str))]
+    pub build_directory: Option<OsString>,
+
+    /// Output format: human, json or gcc.
+    #[structopt(long = "format", default_value = "human")]
+    pub format: ReportFormat,
+
+    /// Encoding of the log files: utf-8, latin-1 or cp1252.
+    #[structopt(long = "log-encoding", default_value = "utf-8")]
+    pub log_encoding: Encoding,
+
+    #[structopt(flatten)]
+    pub badboxes: BadboxOptions,
+
+    #[structopt(flatten)]
+    pub suppress: SuppressOptions,
+
+    /// Log files, or the documents whose log files should
+    /// be read.
+    #[structopt(name = "files", parse(from_os_str))]
+    pub files: Vec<PathBuf>,
+}
+
+#[derive(StructOpt)]
+pub struct CheckOptions {
+    /// Directory in which the build occurred.
+    #[structopt(long = "build-dir", parse(from_os_str))]
+    pub build_directory: Option<OsString>,
+
+    /// Maximum number of errors allowed.
+    #[structopt(long = "max-errors", default_value = "0")]
+    pub max_errors: usize,
+
+    /// Maximum number of warnings allowed.
+    #[structopt(long = "max-warnings")]
+    pub max_warnings: Option<usize>,
+
+    /// Maximum number of bad boxes allowed.
+    #[structopt(long = "max-badboxes")]
+    pub max_badboxes: Option<usize>,
+
+    /// Maximum number of undefined references and
+    /// citations allowed.
+    #[structopt(long = "max-missing-references")]
+    pub max_missing_references: Option<usize>,
+
+    /// Write every message found to a baseline file, which
+    /// can be given to --baseline to only check for new
+    /// messages.
+    #[structopt(long = "write-baseline", parse(from_os_str))]
+    pub write_baseline: Option<PathBuf>,
+
+    /// Record the versions of the packages listed by
+    /// `\listfiles` in the lockfile, instead of checking them.
+    #[structopt(long = "update-lockfile")]
+    pub update_lockfile: bool,
+
+    /// Encoding of the log files: utf-8, latin-1 or cp1252.
+    #[structopt(long = "log-encoding", default_value = "utf-8")]
+    pub log_encoding: Encoding,
+
+    #[structopt(flatten)]
+    pub badboxes: BadboxOptions,
+
+    #[structopt(flatten)]
+    pub suppress: SuppressOptions,
+
+    #[structopt(flatten)]
+    pub lock: LockOptions,
+
+    /// Log files, or the documents whose log files should
+    /// be read.
+    #[structopt(name = "files", parse(from_os_str))]
+    pub files: Vec<PathBuf>,
+}
+
+#[derive(StructOpt)]
+pub struct DepsOptions {
+    /// Directory in which the build occurred.
+    #[structopt(long = "build-dir", parse(from_os_str))]
+    pub build_directory: Option<OsString>,
+
+    /// Include files from the TeX distribution.
+    #[structopt(long = "all")]
+    pub all: bool,
+
+    /// Documents whose inputs should be listed.
+    #[structopt(name = "files", parse(from_os_str))]
+    pub files: Vec<PathBuf>,
+}
+
+#[derive(StructOpt)]
+pub struct ExplainOptions {
+    /// Code of the error, such as E002.
+    #[structopt(name = "code")]
+    pub code: Option<String>,
+}
+
+pub fn run() -> Result<(), E> {
+    match CliOptions::from_args() {
+        CliOptions::Build(options) => run_build(options),
+        CliOptions::Clean(options) => run_clean(options),
+        CliOptions::Watch(options) => run_watch(options),
+        CliOptions::Report(options) => run_report(options),
+        CliOptions::Check(options) => run_check(options),
+        CliOptions::Deps(options) => run_deps(options),
+        CliOptions::Explain(options) => run_explain(options),
+    }
+}
+
+fn build_dir(build_directory: &Option<OsString>) -> PathBuf {
+    match build_directory {
+        Some(d) => PathBuf::from(d),
+        None => PathBuf::from("."),
+    }
+}
+
+/// Path of the log of `file`, which may be the log itself or the
+/// document it was produced for.
+fn log_path(file: &Path, build_directory: &Option<OsString>) -> PathBuf {
+    if file.extension() == Some(OsStr::new("log")) {
+        return file.to_owned();
+    }
+    let stem = file.file_stem().unwrap_or_default().to_string_lossy();
+    build_dir(build_directory).join(format!("{}.log", stem))
+}
+
+fn read_report(
+    path: &Path,
+    document: &Path,
+    encoding: Encoding,
+    badboxes: &BadboxOptions,
+    suppress: &SuppressOptions,
+) -> Result<BuildReport, E> {
+    match File::open(path) {
+        Ok(log) => {
+            let mut report = parse_log_encoded(log, encoding);
+            report.filter_badboxes(&badboxes.thresholds());
+            annotate(&mut report, document);
+            suppress.apply(&mut report);
+            Ok(report)
         }
+        Err(e) => bail!("Could not read {}: {}", path.display(), e),
     }
 }
 
-pub fn run() -> Result<(), E> {
-    let CliOptions { config, files } = CliOptions::from_args();
+fn run_report(options: ReportOptions) -> Result<(), E> {
+    for file in &options.files {
+        let path = log_path(file, &options.build_directory);
+        let report = read_report(
+            &path,
+            &file.with_extension("tex"),
+            options.log_encoding,
+            &options.badboxes,
+            &options.suppress,
+        )?;
+        print!(
+            "{}",
+            format_report(&report, &path.display().to_string(), options.format)
+        );
+        if options.format == ReportFormat::Human && path != *file {
+            let dir = build_dir(&options.build_directory);
+            let jobname = file.file_stem().unwrap_or_default().to_string_lossy();
+            print!("{}", analyse(file, &dir, &jobname, &report));
+        }
+    }
+    Ok(())
+}
+
+fn run_check(options: CheckOptions) -> Result<(), E> {
+    let mut reports = Vec::new();
+    for file in &options.files {
+        let path = log_path(file, &options.build_directory);
+        let report = read_report(
+            &path,
+            &file.with_extension("tex"),
+            options.log_encoding,
+            &options.badboxes,
+            &options.suppress,
+        )?;
+        reports.push((path, report));
+    }
+
+    if let Some(ref baseline) = options.write_baseline {
+        let reports: Vec<BuildReport> = reports.into_iter().map(|(_, r)| r).collect();
+        return write_baseline(baseline, &reports);
+    }
+
+    if options.update_lockfile {
+        let mut lock = options.lock.lockfile.lock.clone().unwrap_or_default();
+        for (_, report) in &reports {
+            lock.update(report);
+        }
+        return lock.write(&options.lock.lockfile.path);
+    }
+
+    let mut failed = 0;
+    for (path, report) in &reports {
+        let missing = report.missing_references + report.missing_citations;
+        let limits = [
+            ("errors", report.errors, Some(options.max_errors)),
+            ("warnings", report.warnings, options.max_warnings),
+            ("bad boxes", report.badboxes, options.max_badboxes),
+            ("missing references", missing, options.max_missing_references),
+        ];
+
+        let mut passed = true;
+        if let Some(kind) = report.failure.filter(|k| k.is_fatal()) {
+            println!("{}: build failed ({})", path.display(), kind);
+            passed = false;
+        }
+        for drift in options.lock.drift(report) {
+            println!("{}: {}", path.display(), drift);
+            if options.lock.locked {
+                passed = false;
+            }
+        }
+        for (name, count, limit) in limits.iter() {
+            if let Some(limit) = limit {
+                if count > limit {
+                    println!("{}: {} {} (limit {})", path.display(), count, name, limit);
+                    passed = false;
+                }
+            }
+        }
+        if !passed {
+            failed += 1;
+        }
+    }
+
+    if failed > 0 {
+        bail!("{} of {} documents failed the check", failed, options.files.len());
+    }
+    Ok(())
+}
+
+fn run_explain(options: ExplainOptions) -> Result<(), E> {
+    let code = match options.code {
+        Some(code) => code,
+        None => {
+            for explanation in CATALOGUE {
+                println!("{}  {}", explanation.code, explanation.title);
+            }
+            return Ok(());
+        }
+    };
+    match explain(&code) {
+        Some(explanation) => {
+            print!("{}", explanation);
+            Ok(())
+        }
+        None => bail!("Unknown error code: {} (run `rustex explain` to list them)", code),
+    }
+}
+
+fn run_deps(options: DepsOptions) -> Result<(), E> {
+    let dir = build_dir(&options.build_directory);
+    for file in &options.files {
+        let stem = file.file_stem().unwrap_or_default().to_string_lossy();
+        let fls = dir.join(format!("{}.fls", stem));
+
+        let inputs = if fls.is_file() {
+            let cwd = std::env::current_dir()?;
+            read_recorder_file(&dir, &fls)
+                .inputs
+                .into_iter()
+                .filter_map(|p| match p.strip_prefix(&cwd) {
+                    Ok(relative) => Some(relative.to_owned()),
+                    Err(_) if options.all => Some(p),
+                    Err(_) => None,
+                })
+                .collect()
+        } else {
+            source_files(file)
+        };
+
+        println!("{}", file.display());
+        for input in inputs {
+            println!("  {}", input.display());
+        }
+    }
+    Ok(())
+}
+
+/// Latest modification time of the sources of a document.
+fn last_modified(path: &Path) -> Option<SystemTime> {
+    source_files(path)
+        .iter()
+        .filter_map(|f| fs::metadata(f).and_then(|m| m.modified()).ok())
+        .max()
+}
+
+fn run_watch(options: WatchOptions) -> Result<(), E> {
+    let WatchOptions { build, interval } = options;
+    let conf = Arc::new(build.config);
+    let files = build.files;
+
+    let mut runner = Runner::new(conf, &files);
+    let abort = runner.abort_handle();
+    let interrupted = Arc::new(AtomicBool::new(false));
+    let flag = interrupted.clone();
+    ctrlc::set_handler(move || {
+        flag.store(true, Ordering::SeqCst);
+        abort.abort();
+    })?;
+
+    let mut modified: Vec<Option<SystemTime>> = files.iter().map(|f| last_modified(f)).collect();
+    while !interrupted.load(Ordering::SeqCst) {
+        display_events(&mut runner).finish_and_clear();
+
+        thread::sleep(Duration::from_millis(interval));
+        if interrupted.load(Ordering::SeqCst) {
+            break;
+        }
+        for (file, stamp) in files.iter().zip(modified.iter_mut()) {
+            let current = last_modified(file);
+            if current != *stamp {
+                *stamp = current;
+                // The file may be back by the next change.
+                if let Err(e) = runner.submit(file) {
+                    println!("{}", e);
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Show the progress of the runner until all jobs are complete.
+fn display_events(runner: &mut Runner) -> ProgressBar {
+    let pb = ProgressBar::new(0);
+    pb.set_style(ProgressStyle::default_bar().template("[{bar:30}] {pos}/{len} {msg}"));
+
+    let mut queued = 0;
+    while let Some(event) = runner.next_event() {
+        let job = match runner.job(event.job) {
+            Some(job) => job,
+            None => continue,
+        };
+        match event.kind {
+            EventKind::JobQueued { .. } => {
+                queued += 1;
+                pb.set_length(queued);
+            }
+            EventKind::PassStarted { .. } | EventKind::Progress(_) => {
+                pb.set_message(&job.describe_progress());
+            }
+            EventKind::ToolStarted { tool } => {
+                pb.set_message(&format!("{}: running {}", job.path.display(), tool));
+            }
+            EventKind::JobFinished { .. } => {
+                pb.inc(1);
+                pb.println(job.to_string());
+                if let Some(xref) = job.cross_references() {
+                    pb.println(xref.to_string().trim_end());
+                }
+                for drift in &job.package_drift {
+                    pb.println(format!("  {}", drift));
+                }
+            }
+            _ => {}
+        }
+    }
+    pb
+}
+
+fn run_clean(options: CleanOptions) -> Result<(), E> {
+    let dir = match options.build_directory {
+        Some(ref d) => PathBuf::from(d),
+        None => PathBuf::from("."),
+    };
+    for file in &options.files {
+        let jobname = match file.file_stem() {
+            Some(stem) => stem,
+            None => continue,
+        };
+        for removed in clean(&dir, jobname, options.level, options.dry_run)? {
+            if options.dry_run {
+                println!("Would remove {}", removed.display());
+            } else {
+                println!("Removed {}", removed.display());
+            }
+        }
+    }
+    Ok(())
+}
+
+fn run_build(options: BuildOptions) -> Result<(), E> {
+    let BuildOptions { config, files } = options;
     let conf = Arc::new(config);
 
     // do the setup for verbosity etc.
-    let mut runner = Runner::new(conf.clone(),  
-                                 &files);
-    let pb = ProgressBar::new(files.len() as u64);
-    
-    while let Some(completed) = runner.process_till_next_complete() {
-        pb.tick();
-        pb.println(completed.to_string());
-    }
+    let mut runner = Runner::new(conf, &files);
+    let abort = runner.abort_handle();
+    ctrlc::set_handler(move || abort.abort())?;
+
+    let pb = display_events(&mut runner);
     runner.do_cleanup()?;
 
     let report = runner.build_report()?;
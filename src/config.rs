@@ -1,8 +1,99 @@
 use std::ffi::OsString;
+use std::path::{Path, PathBuf};
 use std::process::{Command, Stdio};
 
 use structopt::StructOpt;
 
+use outparse::{BadboxThresholds, BuildReport, Encoding};
+
+use crate::clean::CleanLevel;
+use crate::dependencies::document_key;
+use crate::lockfile::{Drift, Lockfile, LockfileArg, LOCKFILE_NAME};
+use crate::suppress::{self, Baseline, Rule};
+
+/// Which bad boxes are worth reporting.
+#[derive(StructOpt, Debug, Default)]
+pub struct BadboxOptions {
+    /// Hide overfull boxes that are overfull by less than
+    /// this many points.
+    #[structopt(long = "min-overfull", default_value = "0")]
+    pub min_overfull: f64,
+
+    /// Hide underfull boxes with a badness below this.
+    ///
+    /// Badness ranges from 0 to 10000.
+    #[structopt(long = "min-badness", default_value = "0")]
+    pub min_badness: u32,
+}
+
+impl BadboxOptions {
+    pub fn thresholds(&self) -> BadboxThresholds {
+        BadboxThresholds {
+            min_overfull: self.min_overfull,
+            min_badness: self.min_badness,
+        }
+    }
+}
+
+/// Which messages to leave out of reports.
+#[derive(StructOpt, Debug, Default)]
+pub struct SuppressOptions {
+    /// Suppress messages matching a rule.
+    ///
+    /// Rules are "package:<name>", "message:<regex>",
+    /// "file:<glob>" or "category:<category>". Multiple calls
+    /// add more rules. Messages about source lines marked with
+    /// `% rustex-ignore` are always suppressed. Errors are never
+    /// suppressed.
+    #[structopt(long = "ignore")]
+    pub rules: Vec<Rule>,
+
+    /// Suppress the messages listed in a baseline file, as
+    /// written by `rustex check --write-baseline`.
+    #[structopt(long = "baseline", parse(try_from_os_str = "Baseline::read"))]
+    pub baseline: Option<Baseline>,
+}
+
+impl SuppressOptions {
+    pub fn apply(&self, report: &mut BuildReport) {
+        suppress::apply(report, &self.rules, self.baseline.as_ref());
+    }
+}
+
+/// Checking the versions of packages against a lockfile.
+#[derive(StructOpt, Debug, Default)]
+pub struct LockOptions {
+    /// Lockfile recording the versions of the packages listed
+    /// by `\listfiles`.
+    ///
+    /// Packages whose versions differ from the lockfile are
+    /// reported, when it exists.
+    #[structopt(
+        long = "lockfile",
+        raw(default_value = "LOCKFILE_NAME"),
+        parse(try_from_os_str = "LockfileArg::read")
+    )]
+    pub lockfile: LockfileArg,
+
+    /// Fail when package versions differ from the lockfile,
+    /// rather than warn. Without a lockfile, every package
+    /// differs.
+    #[structopt(long = "locked")]
+    pub locked: bool,
+}
+
+impl LockOptions {
+    /// Packages of `report` whose versions differ from the
+    /// lockfile.
+    pub fn drift(&self, report: &BuildReport) -> Vec<Drift> {
+        match self.lockfile.lock {
+            Some(ref lock) => lock.drift(report),
+            None if self.locked => Lockfile::default().drift(report),
+            None => Vec::new(),
+        }
+    }
+}
+
 #[derive(StructOpt, Debug)]
 pub struct Config {
     /// Use verbose mode.
@@ -32,16 +123,46 @@ pub struct Config {
 
     /// Clean build directory after build.
     ///
-    /// If selected, the auxiliary files, log files, and
-    /// other output files (excluding the output of LaTeX)
-    /// will be removed from the build directory. Unless
-    /// a second build is forced, a second build run will
-    /// only be executed if there are unresolved warnings.
+    /// If selected, the files generated by the build will
+    /// be removed from the build directory, as chosen by
+    /// --clean-level. Unless a second build is forced, a
+    /// second build run will only be executed if there are
+    /// unresolved warnings.
     #[structopt(long = "clean")]
     pub clean_build: bool,
 
+    /// Which files to remove when cleaning.
+    ///
+    /// One of "aux" (auxiliary files only), "keep-output"
+    /// (everything except the output of LaTeX) or "full".
+    #[structopt(long = "clean-level", default_value = "aux")]
+    pub clean_level: CleanLevel,
+
     #[structopt(short = "j", long = "jobs", default_value="1")]
-    pub max_jobs: usize
+    pub max_jobs: usize,
+
+    /// Declare that a document depends on another.
+    ///
+    /// Given as `document.tex:prerequisite.tex`, the prerequisite
+    /// is built before the document. Dependencies on other documents
+    /// used through `\externaldocument`, `\includegraphics`,
+    /// `\includepdf` or `\input` are detected automatically.
+    #[structopt(long = "depends")]
+    pub dependencies: Vec<String>,
+
+    /// Encoding of the output of the engine: utf-8,
+    /// latin-1 or cp1252.
+    #[structopt(long = "log-encoding", default_value = "utf-8")]
+    pub log_encoding: Encoding,
+
+    #[structopt(flatten)]
+    pub badboxes: BadboxOptions,
+
+    #[structopt(flatten)]
+    pub suppress: SuppressOptions,
+
+    #[structopt(flatten)]
+    pub lock: LockOptions,
 }
 
 impl Default for Config {
@@ -51,8 +172,14 @@ impl Default for Config {
             flags: vec![],
             build_directory: None,
             clean_build: false,
+            clean_level: CleanLevel::default(),
             //verbose: false,
-            max_jobs: 1
+            max_jobs: 1,
+            dependencies: vec![],
+            log_encoding: Encoding::default(),
+            badboxes: BadboxOptions::default(),
+            suppress: SuppressOptions::default(),
+            lock: LockOptions::default(),
         }
     }
 }
@@ -64,6 +191,7 @@ impl Config {
             cmd.arg(f);
         }
         cmd.arg(OsString::from("-interaction=nonstopmode"));
+        cmd.arg(OsString::from("-recorder"));
         if let Some(ref p) = self.build_directory {
             let mut flag = OsString::from("-output-directory=");
             flag.push(p.as_os_str());
@@ -73,4 +201,46 @@ impl Config {
         cmd.stderr(Stdio::inherit());
         cmd
     }
+
+    /// Prerequisites declared for the document at `path`, as
+    /// returned by [`document_key`].
+    pub fn declared_dependencies(&self, path: &Path) -> Vec<PathBuf> {
+        let key = document_key(path);
+        self.dependencies
+            .iter()
+            .filter_map(|d| {
+                // Split on the last colon, as the document may start
+                // with a Windows drive letter.
+                let (document, prerequisite) = d.rsplit_once(':')?;
+                let (document, prerequisite) = (Path::new(document), Path::new(prerequisite));
+                if document_key(document) == key {
+                    Some(document_key(prerequisite))
+                } else {
+                    None
+                }
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_declared_dependencies_with_drive_letter() {
+        let config = Config {
+            dependencies: vec![String::from("C:\\docs\\a.tex:b.tex"), String::from("c.tex:a.tex")],
+            ..Config::default()
+        };
+
+        assert_eq!(
+            config.declared_dependencies(Path::new("C:\\docs\\a.tex")),
+            vec![document_key(Path::new("b.tex"))]
+        );
+        assert_eq!(
+            config.declared_dependencies(Path::new("c.tex")),
+            vec![document_key(Path::new("a.tex"))]
+        );
+    }
 }
@@ -0,0 +1,242 @@
+use std::collections::HashSet;
+use std::env;
+use std::fs;
+use std::path::{Component, Path, PathBuf};
+
+use lazy_static::lazy_static;
+use regex::Regex;
+
+lazy_static! {
+    /// Commands that pull in the output of another document.
+    static ref EXTERNAL: Regex = Regex::new(
+        r#"\\(?:externaldocument|externalcitedocument|includegraphics|includepdf|includestandalone)\*?(?:\[[^\]]*\])*\{([^}]+)\}"#
+    ).unwrap();
+
+    /// Commands that read another source file.
+    static ref INPUT: Regex = Regex::new(
+        r#"\\(?:input|include|subfile)\{([^}]+)\}"#
+    ).unwrap();
+}
+
+/// Files named in a source file, split by how they are used.
+#[derive(Debug, Default, PartialEq)]
+pub struct SourceReferences {
+    /// Outputs of other documents, such as figures or `.aux` files
+    /// for cross references.
+    pub external: Vec<String>,
+    /// Other source files read by this one.
+    pub inputs: Vec<String>,
+}
+
+/// Remove a TeX comment from the end of a line.
+pub(crate) fn strip_comment(line: &str) -> &str {
+    let mut escaped = false;
+    for (i, c) in line.char_indices() {
+        match c {
+            '\\' => escaped = !escaped,
+            '%' if !escaped => return &line[..i],
+            _ => escaped = false,
+        }
+    }
+    line
+}
+
+pub fn scan_source(source: &str) -> SourceReferences {
+    let mut refs = SourceReferences::default();
+    for line in source.lines().map(strip_comment) {
+        for m in EXTERNAL.captures_iter(line) {
+            refs.external.push(m[1].trim().to_owned());
+        }
+        for m in INPUT.captures_iter(line) {
+            let name = m[1].trim().to_owned();
+            refs.external.push(name.clone());
+            refs.inputs.push(name);
+        }
+    }
+    refs
+}
+
+/// Files read and written by a build, as listed in the `.fls` file
+/// produced by the `-recorder` option of the engine.
+#[derive(Debug, Default)]
+pub struct Recorded {
+    pub inputs: Vec<PathBuf>,
+    pub outputs: Vec<PathBuf>,
+}
+
+/// Read a `.fls` file, resolving relative paths against the working
+/// directory it records, or `dir` if it has none. Each file is only
+/// listed once, in the order it was first recorded.
+pub fn read_recorder_file(dir: &Path, fls: &Path) -> Recorded {
+    let mut recorded = Recorded::default();
+    let contents = match fs::read_to_string(fls) {
+        Ok(contents) => contents,
+        Err(_) => return recorded,
+    };
+
+    let mut pwd = dir.to_owned();
+    for line in contents.lines() {
+        let (list, path) = if let Some(p) = line.strip_prefix("PWD ") {
+            pwd = PathBuf::from(p);
+            continue;
+        } else if let Some(p) = line.strip_prefix("INPUT ") {
+            (&mut recorded.inputs, pwd.join(p))
+        } else if let Some(p) = line.strip_prefix("OUTPUT ") {
+            (&mut recorded.outputs, pwd.join(p))
+        } else {
+            continue;
+        };
+        if !list.contains(&path) {
+            list.push(path);
+        }
+    }
+    recorded
+}
+
+fn resolve_input(dir: &Path, name: &str) -> PathBuf {
+    let path = dir.join(name);
+    if path.extension().is_none() {
+        path.with_extension("tex")
+    } else {
+        path
+    }
+}
+
+/// Source files read while building the document at `path`, found
+/// by following `\input` and `\include` commands from the main file.
+///
+/// The main file is included. Files that cannot be read are skipped.
+pub fn source_files(path: &Path) -> Vec<PathBuf> {
+    let dir = path.parent().unwrap_or_else(|| Path::new(""));
+    let mut seen = HashSet::new();
+    let mut queue = vec![path.to_owned()];
+    let mut files = Vec::new();
+
+    while let Some(file) = queue.pop() {
+        if !seen.insert(file.clone()) {
+            continue;
+        }
+        let source = match fs::read_to_string(&file) {
+            Ok(source) => source,
+            Err(_) => continue,
+        };
+        for name in scan_source(&source).inputs {
+            queue.push(resolve_input(dir, &name));
+        }
+        files.push(file);
+    }
+    files
+}
+
+/// Extensions of the files a document is built from or writes, and
+/// so of the names that can refer to another document.
+const DOCUMENT_EXTENSIONS: &[&str] = &["tex", "pdf", "dvi", "aux"];
+
+/// `path` made absolute with `.` and `..` removed, and with the
+/// symbolic links of its directory resolved when it exists, so that
+/// two names of the same file compare equal.
+pub(crate) fn normalise(path: &Path) -> PathBuf {
+    let path = env::current_dir()
+        .map(|cwd| cwd.join(path))
+        .unwrap_or_else(|_| path.to_owned());
+    let mut normal = PathBuf::new();
+    for component in path.components() {
+        match component {
+            Component::CurDir => {}
+            Component::ParentDir => {
+                normal.pop();
+            }
+            c => normal.push(c),
+        }
+    }
+    match (normal.parent(), normal.file_name()) {
+        (Some(dir), Some(name)) => fs::canonicalize(dir)
+            .map(|dir| dir.join(name))
+            .unwrap_or(normal),
+        _ => normal,
+    }
+}
+
+/// The path that identifies a document in dependencies: its
+/// normalised path without the extension.
+pub fn document_key(path: &Path) -> PathBuf {
+    normalise(&path.with_extension(""))
+}
+
+/// Documents whose output is used by the document at `path`, or by
+/// any of the sources it inputs, as returned by [`document_key`].
+///
+/// Names are resolved against the directory of the document. Names
+/// with an extension that no document is built from or writes, such
+/// as `plot.png`, are skipped.
+pub fn referenced_documents(path: &Path) -> Vec<PathBuf> {
+    let dir = path.parent().unwrap_or_else(|| Path::new(""));
+    let mut documents = Vec::new();
+    for file in source_files(path) {
+        let source = match fs::read_to_string(&file) {
+            Ok(source) => source,
+            Err(_) => continue,
+        };
+        for name in scan_source(&source).external {
+            let name = Path::new(&name);
+            let is_document = name
+                .extension()
+                .is_none_or(|e| DOCUMENT_EXTENSIONS.contains(&e.to_string_lossy().to_lowercase().as_str()));
+            if !is_document {
+                continue;
+            }
+            let key = document_key(&dir.join(name));
+            if !documents.contains(&key) {
+                documents.push(key);
+            }
+        }
+    }
+    documents
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_scan_external_documents() {
+        let source = "\\externaldocument[A-]{appendix}\n\\includegraphics[width=\\textwidth]{figures/plot.pdf}\n";
+        let refs = scan_source(source);
+
+        assert_eq!(refs.external, vec!["appendix", "figures/plot.pdf"]);
+        assert!(refs.inputs.is_empty());
+    }
+
+    #[test]
+    fn test_scan_ignores_comments() {
+        let source = "\\input{intro} % \\input{old}\n%\\includepdf{draft}\n50\\% \\include{results}\n";
+        let refs = scan_source(source);
+
+        assert_eq!(refs.inputs, vec!["intro", "results"]);
+    }
+
+    #[test]
+    fn test_referenced_documents() {
+        let dir = std::env::temp_dir().join(format!("rustex-dependencies-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(dir.join("paper")).unwrap();
+        fs::create_dir_all(dir.join("appendix")).unwrap();
+        fs::write(
+            dir.join("paper/main.tex"),
+            "\\includegraphics{results}\n\\externaldocument{../appendix/./main}\n\\includegraphics{plot.png}\n",
+        )
+        .unwrap();
+
+        let documents = referenced_documents(&dir.join("paper/main.tex"));
+
+        assert_eq!(
+            documents,
+            vec![
+                document_key(&dir.join("paper/results.tex")),
+                document_key(&dir.join("appendix/main.tex")),
+            ]
+        );
+        assert_ne!(documents[0], document_key(&dir.join("results.tex")));
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -1,6 +1,11 @@
-use std::ffi::OsString;
+use std::ffi::{OsStr, OsString};
+use std::fs;
+use std::path::Path;
 
 use failure::{err_msg, Error};
+use sha2::{Digest, Sha256};
+
+use outparse::BuildOutput;
 
 pub enum LaTeXEngine {
     Pdflatex,
@@ -13,6 +18,169 @@ pub fn get_extension_for_engine(engine: &str) -> Result<OsString, Error> {
         "pdflatex" => Ok(OsString::from(".pdf")),
         "pdftex" => Ok(OsString::from(".pdf")),
         "luatex" => Ok(OsString::from(".pdf")),
+        "lualatex" => Ok(OsString::from(".pdf")),
+        "xelatex" => Ok(OsString::from(".pdf")),
+        "xetex" => Ok(OsString::from(".pdf")),
+        "latex" => Ok(OsString::from(".dvi")),
+        "tex" => Ok(OsString::from(".dvi")),
+        "etex" => Ok(OsString::from(".dvi")),
+        "platex" => Ok(OsString::from(".dvi")),
+        "uplatex" => Ok(OsString::from(".dvi")),
+        "dvilualatex" => Ok(OsString::from(".dvi")),
+        "dviluatex" => Ok(OsString::from(".dvi")),
         _ => Err(err_msg(format!("Unrecognised LaTeX engine: {}", engine))),
     }
 }
+
+/// Extensions of the files `engine` may write, starting with the
+/// one it writes by default. XeTeX writes `.xdv` when run with
+/// `-no-pdf`.
+fn output_extensions(engine: &str) -> Result<Vec<OsString>, Error> {
+    let mut extensions = vec![get_extension_for_engine(engine)?];
+    if engine == "xelatex" || engine == "xetex" {
+        extensions.push(OsString::from(".xdv"));
+    }
+    Ok(extensions)
+}
+
+/// Check that the output of a build exists, with an extension
+/// written by `engine`, and record its path and hash.
+///
+/// The file named in the log is used when there is one, as the job
+/// name may have been changed by the document. It is relative to
+/// `cwd`, the directory the engine ran in, and already includes
+/// any output directory. Its extension is trusted when the engine
+/// is not one this knows about, such as `pdflatex-dev` or a wrapper
+/// script. Otherwise the output is looked for in `dir`.
+pub fn verify_output(
+    output: &mut BuildOutput,
+    cwd: &Path,
+    dir: &Path,
+    jobname: &OsStr,
+    engine: &OsStr,
+) -> Result<(), Error> {
+    let engine = Path::new(engine).file_stem().unwrap_or(engine);
+    let extensions = output_extensions(&engine.to_string_lossy());
+    let path = match (&output.file, &extensions) {
+        (Some(file), _) => cwd.join(file),
+        (None, Ok(extensions)) => {
+            let mut name = jobname.to_owned();
+            name.push(&extensions[0]);
+            cwd.join(dir).join(name)
+        }
+        (None, Err(_)) => {
+            return Err(err_msg(format!(
+                "The log names no output, and the output of {} is not known",
+                engine.to_string_lossy()
+            )))
+        }
+    };
+
+    if let Ok(extensions) = extensions {
+        let extension = path.extension().map(|e| format!(".{}", e.to_string_lossy()));
+        if !extensions
+            .iter()
+            .any(|e| Some(e.to_string_lossy().into_owned()) == extension)
+        {
+            return Err(err_msg(format!(
+                "Output {} does not have the extension {}",
+                path.display(),
+                extensions[0].to_string_lossy()
+            )));
+        }
+    }
+    let contents = fs::read(&path)
+        .map_err(|e| err_msg(format!("Could not read output {}: {}", path.display(), e)))?;
+
+    let hash = Sha256::digest(&contents);
+    output.sha256 = Some(hash.iter().map(|b| format!("{:02x}", b)).collect());
+    output.path = Some(path.to_string_lossy().into_owned());
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_verify_output() {
+        let dir = std::env::temp_dir().join(format!("rustex-engine-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(dir.join("main.pdf"), "abc").unwrap();
+        let here = Path::new(".");
+
+        let mut output = BuildOutput::default();
+        verify_output(&mut output, &dir, here, OsStr::new("main"), OsStr::new("/usr/bin/pdflatex")).unwrap();
+        assert_eq!(
+            output.sha256.as_deref(),
+            Some("ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad")
+        );
+
+        let mut missing = BuildOutput {
+            file: Some(String::from("other.pdf")),
+            ..BuildOutput::default()
+        };
+        assert!(verify_output(&mut missing, &dir, here, OsStr::new("main"), OsStr::new("pdflatex")).is_err());
+        assert!(verify_output(&mut output, &dir, here, OsStr::new("main"), OsStr::new("echo")).is_err());
+
+        fs::write(dir.join("main.dvi"), "abc").unwrap();
+        let mut dvi = BuildOutput::default();
+        verify_output(&mut dvi, &dir, here, OsStr::new("main"), OsStr::new("latex")).unwrap();
+        assert!(dvi.path.unwrap().ends_with("main.dvi"));
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_verify_output_in_build_directory() {
+        let dir = std::env::temp_dir().join(format!("rustex-engine-build-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(dir.join("build")).unwrap();
+        fs::write(dir.join("build/main.pdf"), "abc").unwrap();
+        let build = Path::new("build");
+
+        // The log names the output with the build directory.
+        let mut output = BuildOutput {
+            file: Some(String::from("build/main.pdf")),
+            ..BuildOutput::default()
+        };
+        verify_output(&mut output, &dir, build, OsStr::new("main"), OsStr::new("pdflatex")).unwrap();
+        assert_eq!(output.path, Some(dir.join("build/main.pdf").to_string_lossy().into_owned()));
+
+        let mut unnamed = BuildOutput::default();
+        verify_output(&mut unnamed, &dir, build, OsStr::new("main"), OsStr::new("pdflatex")).unwrap();
+        assert_eq!(unnamed.path, output.path);
+
+        let mut xdv = BuildOutput {
+            file: Some(String::from("build/main.xdv")),
+            ..BuildOutput::default()
+        };
+        fs::write(dir.join("build/main.xdv"), "abc").unwrap();
+        verify_output(&mut xdv, &dir, build, OsStr::new("main"), OsStr::new("xelatex")).unwrap();
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_verify_output_of_unknown_engine() {
+        let dir = std::env::temp_dir().join(format!("rustex-engine-unknown-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(dir.join("main.pdf"), "abc").unwrap();
+        let here = Path::new(".");
+        let engine = OsStr::new("/opt/bin/pdflatex-dev");
+
+        let mut output = BuildOutput {
+            file: Some(String::from("main.pdf")),
+            ..BuildOutput::default()
+        };
+        verify_output(&mut output, &dir, here, OsStr::new("main"), engine).unwrap();
+        assert!(output.sha256.is_some());
+
+        let mut missing = BuildOutput {
+            file: Some(String::from("other.pdf")),
+            ..BuildOutput::default()
+        };
+        assert!(verify_output(&mut missing, &dir, here, OsStr::new("main"), engine).is_err());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,99 @@
+use std::collections::VecDeque;
+use std::fmt;
+use std::path::PathBuf;
+use std::time::SystemTime;
+
+use serde::Serialize;
+
+use outparse::Message;
+
+use crate::jobs::{JobProgress, JobStatus};
+
+pub(crate) type EventQueue = VecDeque<RunnerEvent>;
+
+/// Auxiliary programs that are run between passes of the engine.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub enum Tool {
+    Bibtex,
+    Makeindex,
+}
+
+impl Tool {
+    pub fn program(self) -> &'static str {
+        match self {
+            Tool::Bibtex => "bibtex",
+            Tool::Makeindex => "makeindex",
+        }
+    }
+
+    /// Variables holding the paths the program searches for its
+    /// input, such as bibliographies and styles.
+    pub fn search_variables(self) -> &'static [&'static str] {
+        match self {
+            Tool::Bibtex => &["BIBINPUTS", "BSTINPUTS"],
+            Tool::Makeindex => &["INDEXSTYLE"],
+        }
+    }
+}
+
+impl fmt::Display for Tool {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.program())
+    }
+}
+
+/// Why a pass of the engine was started.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub enum PassReason {
+    /// First pass of the job.
+    Initial,
+    /// The previous pass left references or citations unresolved.
+    UnresolvedReferences,
+    /// An auxiliary program has produced new input for the engine.
+    AfterTool(Tool),
+}
+
+impl fmt::Display for PassReason {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            PassReason::Initial => write!(f, "initial pass"),
+            PassReason::UnresolvedReferences => write!(f, "unresolved references"),
+            PassReason::AfterTool(tool) => write!(f, "after running {}", tool),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub enum EventKind {
+    /// The job has been added to the queue.
+    JobQueued { path: PathBuf },
+    /// A pass of the engine has been started.
+    PassStarted { pass: u8, reason: PassReason },
+    /// The page or input file of the current pass changed.
+    Progress(JobProgress),
+    /// A message has been parsed from the output of the engine.
+    MessageParsed(Message),
+    ToolStarted { tool: Tool },
+    ToolFinished { tool: Tool, success: bool },
+    /// The job is complete, and will not be run again.
+    JobFinished { status: JobStatus },
+}
+
+/// Something that happened to one of the jobs of a runner.
+#[derive(Debug, Clone, Serialize)]
+pub struct RunnerEvent {
+    pub time: SystemTime,
+    /// Id of the job the event refers to.
+    pub job: usize,
+    pub kind: EventKind,
+}
+
+impl RunnerEvent {
+    pub fn new(job: usize, kind: EventKind) -> RunnerEvent {
+        RunnerEvent {
+            time: SystemTime::now(),
+            job,
+            kind,
+        }
+    }
+}
@@ -1,17 +1,30 @@
+use std::collections::VecDeque;
+use std::env;
 use std::ffi::OsString;
 use std::fmt;
 use std::fs;
 use std::path::{Path, PathBuf};
-use std::process::{Child as ChildProcess, ChildStdout, Command};
-use std::sync::Arc;
+use std::process::{Child as ChildProcess, Command, ExitStatus, Stdio};
+use std::sync::mpsc::Sender;
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, SystemTime};
 
 use failure::{err_msg, Error};
+use serde::Serialize;
 
-use outparse::{parse_log, BuildReport};
+use outparse::{parse_log_encoded, parse_log_streaming_encoded, BuildReport, FailureKind, ParseEvent};
 
+use crate::clean::{clean, CleanLevel};
 use crate::config::Config;
+use crate::dependencies::{document_key, normalise, referenced_documents};
+use crate::engine::verify_output;
+use crate::lockfile::Drift;
+use crate::events::{EventKind, EventQueue, PassReason, RunnerEvent, Tool};
+use crate::report::annotate;
+use crate::xref::{analyse, XrefReport};
 
-#[derive(Debug)]
+#[derive(Debug, Clone, Serialize)]
 pub enum JobStatus {
     Pending,
     Active,
@@ -33,15 +46,99 @@ impl fmt::Display for JobStatus {
     }
 }
 
+/// Messages sent back to the runner by the threads that watch
+/// over running jobs.
+#[derive(Debug)]
+pub(crate) enum JobMessage {
+    /// Something of interest has been found in the output of
+    /// the engine.
+    Progress { id: usize, event: ParseEvent },
+    /// The engine closed its output stream, carrying the report
+    /// parsed from the log as it was written.
+    Finished { id: usize, report: Box<BuildReport> },
+    /// An auxiliary program run between passes has exited.
+    ToolFinished { id: usize, tool: Tool, success: bool },
+    /// Stop all running jobs and discard pending ones.
+    Abort,
+}
+
+/// Progress of the current pass of a job, as read from the log.
+#[derive(Debug, Default, Clone, Serialize)]
+pub struct JobProgress {
+    pub page: Option<usize>,
+    pub file: Option<String>,
+}
+
+impl JobProgress {
+    /// Update the progress from a parse event, returning true if
+    /// anything changed.
+    pub fn update(&mut self, event: &ParseEvent) -> bool {
+        match event {
+            ParseEvent::Page(page) => self.page = Some(*page),
+            ParseEvent::FileOpened(file) => self.file = Some(file.clone()),
+            ParseEvent::FileClosed(file) => self.file = file.clone(),
+            ParseEvent::Message(_) => return false,
+        }
+        true
+    }
+}
+
+/// What a job has to do next, once a pass or tool has finished.
+///
+/// This is shared by the synchronous and asynchronous runners,
+/// which only differ in how they execute each step.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum NextStep {
+    Pass(PassReason),
+    Tool(Tool),
+    Done,
+}
+
+/// Whether the prerequisites of a job have been built.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Prerequisites {
+    Ready,
+    Waiting,
+    Failed,
+}
+
+/// How often a running tool is checked for having exited.
+const TOOL_POLL_INTERVAL: Duration = Duration::from_millis(20);
+
 #[derive(Debug)]
 pub struct Job {
     config: Arc<Config>,
+    pub id: usize,
+    pub path: PathBuf,
     pub jobname: OsString,
-    command: Command,
     child: Option<ChildProcess>,
+    /// BibTeX or makeindex, while it runs.
+    tool: Option<Arc<Mutex<ChildProcess>>>,
     pub run_count: u8,
     pub report: Option<BuildReport>,
     pub status: JobStatus,
+    /// Why the last pass failed, as found in its log.
+    pub failure: Option<FailureKind>,
+    /// Packages whose versions differ from the lockfile.
+    pub package_drift: Vec<Drift>,
+    pub progress: JobProgress,
+    /// Documents that must be built first, as returned by
+    /// [`document_key`].
+    pub depends_on: Vec<PathBuf>,
+    /// Identifies this document in the references of others.
+    pub(crate) key: PathBuf,
+    /// Identifies the output of this document, which is written
+    /// to the build directory, in the references of others.
+    pub(crate) output_key: PathBuf,
+    references: Vec<PathBuf>,
+    last_pass: PassReason,
+    tools: VecDeque<Tool>,
+    /// Set when the runner is aborted, so that the job stops once
+    /// the current pass or tool has exited.
+    aborted: bool,
+    /// Modification time and size of the log file before the current
+    /// pass, to tell whether the pass wrote a new one.
+    log_before: Option<(SystemTime, u64)>,
 }
 
 impl fmt::Display for Job {
@@ -55,86 +152,411 @@ impl fmt::Display for Job {
 }
 
 impl Job {
-    pub fn new(config: Arc<Config>, path: &Path) -> Job {
-        let mut command = config.get_command();
-        command.arg(&path);
+    pub fn new(id: usize, config: Arc<Config>, path: &Path) -> Job {
+        let jobname = path.file_stem().unwrap().to_owned();
+        let mut references = config.declared_dependencies(path);
+        references.extend(referenced_documents(path));
+        let output_dir = match &config.build_directory {
+            Some(d) => PathBuf::from(d),
+            None => PathBuf::from("."),
+        };
+        let output_key = normalise(&output_dir.join(&jobname));
         Job {
             config,
-            jobname: path.file_stem().unwrap().to_owned(),
-            command,
+            id,
+            path: path.to_owned(),
+            jobname,
             child: None,
+            tool: None,
             run_count: 0,
             report: None,
             status: JobStatus::Pending,
+            failure: None,
+            package_drift: Vec::new(),
+            progress: JobProgress::default(),
+            depends_on: Vec::new(),
+            key: document_key(path),
+            output_key,
+            references,
+            last_pass: PassReason::Initial,
+            tools: VecDeque::new(),
+            aborted: false,
+            log_before: None,
         }
     }
 
-    fn stdout(&mut self) -> Option<ChildStdout> {
-        if let Some(ref mut child) = self.child {
-            child.stdout.take()
-        } else {
-            None
+    /// Keep the documents this job refers to that are also built by
+    /// the runner, given by their keys and output keys, as its
+    /// dependencies.
+    pub(crate) fn resolve_dependencies(&mut self, documents: &[(&PathBuf, &PathBuf)]) {
+        let mut depends_on = Vec::new();
+        for reference in &self.references {
+            let document = documents
+                .iter()
+                .find(|(key, output)| *key == reference || *output == reference);
+            if let Some((key, _)) = document {
+                if **key != self.key && !depends_on.contains(*key) {
+                    depends_on.push((*key).clone());
+                }
+            }
         }
+        self.depends_on = depends_on;
     }
 
-    fn check_build_log(&mut self, exit_code_success: bool) -> bool {
-        let stdout = self.stdout().unwrap();
-        self.report = Some(parse_log(stdout));
+    /// Whether the documents this job depends on have been built,
+    /// given which documents are still to be built and which have
+    /// failed.
+    pub(crate) fn prerequisites<Q, F>(&self, queued: Q, failed: F) -> Prerequisites
+    where
+        Q: Fn(&PathBuf) -> bool,
+        F: Fn(&PathBuf) -> bool,
+    {
+        let mut state = Prerequisites::Ready;
+        for name in &self.depends_on {
+            if queued(name) {
+                state = Prerequisites::Waiting;
+            } else if failed(name) {
+                return Prerequisites::Failed;
+            }
+        }
+        state
+    }
+
+    /// Short description of where the current pass has got to,
+    /// e.g. `main.tex: pass 2, page 37, chapters/results.tex`.
+    pub fn describe_progress(&self) -> String {
+        let mut desc = format!("{}: pass {}", self.path.display(), self.run_count);
+        if let Some(page) = self.progress.page {
+            desc.push_str(&format!(", page {}", page));
+        }
+        if let Some(ref file) = self.progress.file {
+            desc.push_str(&format!(", {}", file));
+        }
+        desc
+    }
+
+    fn output_dir(&self) -> PathBuf {
+        match &self.config.build_directory {
+            Some(d) => PathBuf::from(d),
+            None => PathBuf::from("."),
+        }
+    }
+
+    fn output_file(&self, extension: &str) -> PathBuf {
+        let mut name = self.jobname.clone();
+        name.push(".");
+        name.push(extension);
+        self.output_dir().join(name)
+    }
+
+    /// Auxiliary programs that need to run after the first pass.
+    fn required_tools(&self) -> VecDeque<Tool> {
+        let mut tools = VecDeque::new();
+        let aux = fs::read_to_string(self.output_file("aux")).unwrap_or_default();
+        if aux.contains("\\bibdata{") {
+            tools.push_back(Tool::Bibtex);
+        }
+        if self.output_file("idx").exists() {
+            tools.push_back(Tool::Makeindex);
+        }
+        tools
+    }
+
+    pub(crate) fn engine_command(&self) -> Command {
+        let mut command = self.config.get_command();
+        command.arg(&self.path);
+        command
+    }
+
+    pub(crate) fn tool_command(&self, tool: Tool) -> Command {
+        let mut command = Command::new(tool.program());
+        match tool {
+            Tool::Bibtex => command.arg(&self.jobname),
+            Tool::Makeindex => command.arg(self.output_file("idx").file_name().unwrap()),
+        };
+        // The tool runs in the build directory, so files next to
+        // the document have to be found through the search paths.
+        for variable in tool.search_variables() {
+            command.env(variable, search_path(variable, &self.source_dir()));
+        }
+        command.current_dir(self.output_dir());
+        command.stdout(Stdio::null());
+        command
+    }
+
+    /// Directory of the document, as an absolute path.
+    fn source_dir(&self) -> PathBuf {
+        let dir = match self.path.parent() {
+            Some(dir) if dir != Path::new("") => dir,
+            _ => Path::new("."),
+        };
+        env::current_dir().map(|cwd| cwd.join(dir)).unwrap_or_else(|_| dir.to_owned())
+    }
+
+    /// Record the start of a new pass, returning the event that
+    /// announces it.
+    pub(crate) fn begin_pass(&mut self, reason: PassReason) -> RunnerEvent {
+        self.log_before = self.log_stamp();
+        self.progress = JobProgress::default();
+        self.status = JobStatus::Active;
+        self.last_pass = reason;
+        self.run_count += 1;
+        RunnerEvent::new(
+            self.id,
+            EventKind::PassStarted {
+                pass: self.run_count,
+                reason,
+            },
+        )
+    }
+
+    fn log_stamp(&self) -> Option<(SystemTime, u64)> {
+        let metadata = fs::metadata(self.output_file("log")).ok()?;
+        Some((metadata.modified().ok()?, metadata.len()))
+    }
+
+    /// Parse the log file written by the last pass.
+    ///
+    /// TeX only writes some things to the log, and not to its
+    /// output: the memory used, missing characters, the contents of
+    /// bad boxes and the help text of errors. Returns `None` if the
+    /// pass did not write a new log.
+    fn read_log(&self) -> Option<BuildReport> {
+        let stamp = self.log_stamp()?;
+        if self.log_before == Some(stamp) {
+            return None;
+        }
+        let log = fs::File::open(self.output_file("log")).ok()?;
+        Some(parse_log_encoded(log, self.config.log_encoding))
+    }
+
+    /// Record the outcome of a pass and decide what to do next.
+    ///
+    /// The report parsed from the output of the engine is replaced
+    /// by the one from its log file, when there is one.
+    pub(crate) fn after_pass(&mut self, exit_code_success: bool, streamed: BuildReport) -> NextStep {
+        let mut report = self.read_log().unwrap_or(streamed);
+        report.filter_badboxes(&self.config.badboxes.thresholds());
+        annotate(&mut report, &self.path);
+        // Whether the pass failed is decided before any messages
+        // are suppressed.
+        self.failure = report.failure;
+        self.config.suppress.apply(&mut report);
+        // The engine runs in the current directory, and names its
+        // output relative to it.
+        let cwd = std::env::current_dir().unwrap_or_else(|_| PathBuf::from("."));
+        if let Err(e) = verify_output(
+            &mut report.output,
+            &cwd,
+            &self.output_dir(),
+            &self.jobname,
+            &self.config.engine,
+        ) {
+            report.output.error = Some(e.to_string());
+        }
+        self.package_drift = self.config.lock.drift(&report);
+        self.report = Some(report);
         let report = self.report.as_ref().unwrap();
 
-        if report.errors > 0 || !exit_code_success {
+        if self.config.lock.locked && !self.package_drift.is_empty() {
+            self.status = JobStatus::Failed;
+            return NextStep::Done;
+        }
+
+        // Recoverable errors count as failures too, as does a pass
+        // that wrote no pages.
+        if report.errors > 0 || self.failure.is_some() || !exit_code_success {
             self.status = JobStatus::Failed;
-            true
-        } else if report.missing_references > 0 && self.run_count == 1 {
-            self.spawn().expect("Could not spawn process");
-            false
+            return NextStep::Done;
+        }
+
+        // So does output named in the log that cannot be read.
+        if report.output.file.is_some() && report.output.error.is_some() {
+            self.status = JobStatus::Failed;
+            return NextStep::Done;
+        }
+
+        let unresolved = match self.last_pass {
+            PassReason::Initial => report.missing_references > 0,
+            PassReason::AfterTool(_) => {
+                report.missing_references > 0 || report.missing_citations > 0
+            }
+            PassReason::UnresolvedReferences => false,
+        };
+
+        if self.run_count == 1 {
+            self.tools = self.required_tools();
+            if let Some(tool) = self.tools.pop_front() {
+                return NextStep::Tool(tool);
+            }
+        }
+
+        if unresolved {
+            NextStep::Pass(PassReason::UnresolvedReferences)
         } else {
             self.status = JobStatus::Success;
-            true
+            NextStep::Done
         }
     }
 
-    pub fn poll(&mut self) -> bool {
-        match self.status {
-            JobStatus::Pending => self.poll_pending(),
-            JobStatus::Active => self.poll_active(),
-            _ => false
+    /// Record the outcome of an auxiliary program and decide what
+    /// to do next.
+    pub(crate) fn after_tool(&mut self, tool: Tool, success: bool) -> NextStep {
+        if !success {
+            self.status = JobStatus::Failed;
+            return NextStep::Done;
+        }
+        match self.tools.pop_front() {
+            Some(next) => NextStep::Tool(next),
+            None => NextStep::Pass(PassReason::AfterTool(tool)),
         }
     }
 
-    fn poll_active(&mut self) -> bool {
-        let child = match self.child {
-            Some(ref mut c) => c,
-            None => return false,
-        };
-        match child.try_wait() {
-            Ok(Some(r)) => self.check_build_log(r.success()),
-            Ok(None) => false,
-            Err(_) => {
-                self.status = JobStatus::Failed;
+    /// Start the next step of the job.
+    ///
+    /// Returns true if the job is complete.
+    fn advance(&mut self, step: NextStep, tx: &Sender<JobMessage>, events: &mut EventQueue) -> bool {
+        if self.aborted && step != NextStep::Done {
+            self.status = JobStatus::Failed;
+            return true;
+        }
+        match step {
+            NextStep::Pass(reason) => {
+                if self.spawn(reason, tx, events).is_err() {
+                    self.status = JobStatus::Failed;
+                    return true;
+                }
                 false
             }
+            NextStep::Tool(tool) => self.start_tool(tool, tx, events),
+            NextStep::Done => true,
         }
     }
 
-    fn poll_pending(&mut self) -> bool {
-        if let Err(e) = self.spawn() {
-            self.status = JobStatus::Failed;
+    /// Handle the end of the current pass, once the output stream
+    /// of the engine has been closed.
+    ///
+    /// Returns true if the job is complete, or false if another
+    /// pass or tool has been started.
+    pub(crate) fn finish(
+        &mut self,
+        report: BuildReport,
+        tx: &Sender<JobMessage>,
+        events: &mut EventQueue,
+    ) -> bool {
+        let exit_code_success = match self.child.take() {
+            Some(mut child) => child.wait().map(|s| s.success()).unwrap_or(false),
+            None => false,
+        };
+        let step = self.after_pass(exit_code_success, report);
+        self.advance(step, tx, events)
+    }
+
+    /// Handle the exit of an auxiliary program, starting the next
+    /// tool or pass as needed.
+    ///
+    /// Returns true if the job is complete.
+    pub(crate) fn finish_tool(
+        &mut self,
+        tool: Tool,
+        success: bool,
+        tx: &Sender<JobMessage>,
+        events: &mut EventQueue,
+    ) -> bool {
+        self.tool = None;
+        events.push_back(RunnerEvent::new(
+            self.id,
+            EventKind::ToolFinished { tool, success },
+        ));
+        let step = self.after_tool(tool, success);
+        self.advance(step, tx, events)
+    }
+
+    fn start_tool(&mut self, tool: Tool, tx: &Sender<JobMessage>, events: &mut EventQueue) -> bool {
+        events.push_back(RunnerEvent::new(self.id, EventKind::ToolStarted { tool }));
+
+        match self.tool_command(tool).spawn() {
+            Ok(child) => {
+                self.watch_tool(tool, child, tx);
+                false
+            }
+            Err(_) => self.finish_tool(tool, false, tx, events),
         }
-        false
     }
 
-    pub fn spawn(&mut self) -> Result<(), Error> {
-        self.child = Some(self.command.spawn()?);
-        self.status = JobStatus::Active;
-        self.run_count += 1;
+    /// Keep the running tool so that it can be killed, and tell the
+    /// runner through `tx` once it has exited.
+    ///
+    /// The tool is polled rather than waited on, so that the lock is
+    /// free for [`kill`](Job::kill) in the meantime.
+    fn watch_tool(&mut self, tool: Tool, child: ChildProcess, tx: &Sender<JobMessage>) {
+        let child = Arc::new(Mutex::new(child));
+        self.tool = Some(child.clone());
+
+        let id = self.id;
+        let tx = tx.clone();
+        thread::spawn(move || {
+            let success = loop {
+                match child.lock().map(|mut c| c.try_wait()) {
+                    Ok(Ok(Some(status))) => break tool_succeeded(tool, status),
+                    Ok(Ok(None)) => thread::sleep(TOOL_POLL_INTERVAL),
+                    _ => break false,
+                }
+            };
+            let _ = tx.send(JobMessage::ToolFinished { id, tool, success });
+        });
+    }
+
+    /// Launch the engine for the next pass.
+    ///
+    /// The output of the engine is parsed by a separate thread as it
+    /// is written, so the pipe never fills up. The runner is sent
+    /// progress through `tx` while the engine runs, and the report
+    /// once the stream is closed.
+    pub(crate) fn spawn(
+        &mut self,
+        reason: PassReason,
+        tx: &Sender<JobMessage>,
+        events: &mut EventQueue,
+    ) -> Result<(), Error> {
+        // The pass begins before the engine can write its log.
+        let started = self.begin_pass(reason);
+        let mut child = self.engine_command().spawn()?;
+        let stdout = match child.stdout.take() {
+            Some(stdout) => stdout,
+            None => {
+                let _ = child.kill();
+                return Err(err_msg("Could not capture the output of the engine"));
+            }
+        };
+
+        let id = self.id;
+        let tx = tx.clone();
+        let encoding = self.config.log_encoding;
+        thread::spawn(move || {
+            let report = parse_log_streaming_encoded(stdout, encoding, |event| {
+                let _ = tx.send(JobMessage::Progress { id, event });
+            });
+            let _ = tx.send(JobMessage::Finished { id, report: Box::new(report) });
+        });
+
+        self.child = Some(child);
+        events.push_back(started);
         Ok(())
     }
 
+    /// Kill the engine or tool, if one is running, and stop the job
+    /// at the end of its current step.
     pub fn kill(&mut self) {
+        self.aborted = true;
         if let Some(ref mut child) = self.child {
             let _ = child.kill();
         }
+        if let Some(ref tool) = self.tool {
+            if let Ok(mut child) = tool.lock() {
+                let _ = child.kill();
+            }
+        }
     }
 
     pub fn get_report(&self) -> Result<&BuildReport, Error> {
@@ -145,24 +567,131 @@ impl Job {
         }
     }
 
-    pub fn cleanup(&mut self) -> Result<(), Error> {
-        let dir = match &self.config.build_directory {
-            Some(d) => PathBuf::from(d),
-            None => PathBuf::from("."),
-        };
-        let name = self.jobname.to_string_lossy();
-        for f in dir.read_dir()?.map(|f| f.unwrap().path()) {
-            if let Some(fname) = f.file_name() {
-                if fname.to_string_lossy().starts_with(name.as_ref()) {
-                    let ext = f.extension().unwrap();
-                    if ext == "tex" || ext == "pdf" {
-                        continue;
-                    }
-                    fs::remove_file(f)?;
-                }
-            }
+    /// Cross references of the last pass checked against the sources,
+    /// if the `.aux` file was written and the analysis found anything.
+    pub fn cross_references(&self) -> Option<XrefReport> {
+        let report = self.report.as_ref()?;
+        if !self.output_file("aux").is_file() {
+            return None;
         }
+        let jobname = self.jobname.to_string_lossy();
+        let xref = analyse(&self.path, &self.output_dir(), &jobname, report);
+        if xref.is_empty() {
+            None
+        } else {
+            Some(xref)
+        }
+    }
 
-        Ok(())
+    /// Remove the files generated by this job, returning the files
+    /// that were removed.
+    pub fn cleanup(&self, level: CleanLevel, dry_run: bool) -> Result<Vec<PathBuf>, Error> {
+        clean(&self.output_dir(), &self.jobname, level, dry_run)
+    }
+}
+
+/// The search path in `variable` with `dir` searched first. The
+/// empty entry added when the variable is not set keeps the default
+/// path of kpathsea.
+fn search_path(variable: &str, dir: &Path) -> OsString {
+    let mut paths = vec![dir.to_owned()];
+    match env::var_os(variable) {
+        Some(current) => paths.extend(env::split_paths(&current)),
+        None => paths.push(PathBuf::new()),
+    }
+    env::join_paths(paths).unwrap_or_else(|_| dir.as_os_str().to_owned())
+}
+
+/// BibTeX exits with status 1 when it only issued warnings.
+pub(crate) fn tool_succeeded(tool: Tool, status: ExitStatus) -> bool {
+    match tool {
+        Tool::Bibtex => status.code().is_some_and(|c| c < 2),
+        Tool::Makeindex => status.success(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::SuppressOptions;
+
+    #[test]
+    fn test_suppressed_error_fails_job() {
+        let config = Arc::new(Config {
+            suppress: SuppressOptions {
+                rules: vec!["file:*.tex".parse().unwrap(), "message:.*".parse().unwrap()],
+                baseline: None,
+            },
+            ..Config::default()
+        });
+        let mut job = Job::new(0, config, Path::new("main.tex"));
+        let report = outparse::parse_log(
+            "(./main.tex\n! LaTeX Error: Environment algn undefined.\n\nl.5 \\begin{algn}\n\nLaTeX Warning: Something odd on input line 9.\n)\n".as_bytes(),
+        );
+
+        assert_eq!(job.after_pass(true, report), NextStep::Done);
+        assert!(matches!(job.status, JobStatus::Failed));
+        let report = job.get_report().unwrap();
+        assert_eq!(report.errors, 1);
+        assert_eq!(report.warnings, 0);
+    }
+
+    #[test]
+    fn test_cross_references_of_clean_build() {
+        let dir = env::temp_dir().join(format!("rustex-job-xref-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(dir.join("main.tex"), "\\label{sec:intro}\n\\cite{knuth}\n").unwrap();
+        let config = Arc::new(Config {
+            build_directory: Some(dir.clone().into_os_string()),
+            ..Config::default()
+        });
+        let mut job = Job::new(0, config, &dir.join("main.tex"));
+        job.report = Some(outparse::parse_log("(./main.tex)\n".as_bytes()));
+
+        assert!(job.cross_references().is_none());
+        fs::write(dir.join("main.aux"), "\\citation{knuth}\n\\bibdata{refs}\n").unwrap();
+        fs::write(dir.join("refs.bib"), "@book{knuth, title={The TeXbook}}\n@book{lamport, title={LaTeX}}\n").unwrap();
+        let xref = job.cross_references().unwrap();
+        assert_eq!(xref.unused_labels, vec!["sec:intro"]);
+        assert_eq!(xref.unused_entries, vec!["lamport"]);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_kill_stops_tool() {
+        let mut job = Job::new(0, Arc::new(Config::default()), Path::new("main.tex"));
+        let (tx, rx) = std::sync::mpsc::channel();
+        let child = Command::new("sleep").arg("30").spawn().unwrap();
+
+        job.watch_tool(Tool::Bibtex, child, &tx);
+        job.kill();
+
+        match rx.recv_timeout(Duration::from_secs(5)).unwrap() {
+            JobMessage::ToolFinished { success, .. } => assert!(!success),
+            message => panic!("unexpected message {:?}", message),
+        }
+    }
+
+    #[test]
+    fn test_tools_search_source_directory() {
+        let config = Arc::new(Config {
+            build_directory: Some(OsString::from("build")),
+            ..Config::default()
+        });
+        let job = Job::new(0, config, Path::new("thesis/main.tex"));
+        let source = env::current_dir().unwrap().join("thesis");
+
+        let command = job.tool_command(Tool::Bibtex);
+        let envs: Vec<_> = command.get_envs().collect();
+        assert_eq!(command.get_current_dir(), Some(Path::new("build")));
+        for variable in &["BIBINPUTS", "BSTINPUTS"] {
+            let (_, value) = envs.iter().find(|(k, _)| k == variable).unwrap();
+            let paths: Vec<PathBuf> = env::split_paths(value.unwrap()).collect();
+            assert_eq!(paths[0], source);
+        }
+
+        let command = job.tool_command(Tool::Makeindex);
+        assert!(command.get_envs().any(|(k, _)| k == "INDEXSTYLE"));
     }
 }
@@ -1,15 +1,28 @@
+#[cfg(feature = "async")]
+pub mod async_runner;
+pub mod clean;
 pub mod cli;
 pub mod config;
+pub mod dependencies;
 pub mod engine;
+pub mod events;
 pub mod jobs;
+pub mod lockfile;
+pub mod missing;
 pub mod report;
 pub mod runner;
+pub mod suppress;
+pub mod unicode;
+pub mod xref;
 
 
 pub use outparse::BuildReport;
 
 pub use engine::LaTeXEngine;
 pub use config::Config;
+pub use events::{EventKind, RunnerEvent};
 pub use runner::Runner;
+#[cfg(feature = "async")]
+pub use async_runner::AsyncRunner;
 pub use report::RunnerReport;
 
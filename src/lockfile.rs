@@ -0,0 +1,180 @@
+use std::collections::BTreeMap;
+use std::ffi::{OsStr, OsString};
+use std::fmt;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use failure::{err_msg, Error};
+
+use outparse::{BuildReport, PackageInfo};
+
+/// Name of the lockfile, by convention.
+pub const LOCKFILE_NAME: &str = "rustex.lock";
+
+const HEADER: &str = "# Package versions recorded by rustex. Update with `rustex check --update-lockfile`.";
+
+/// Versions of the classes, packages and other files used by a set
+/// of documents, as listed by `\listfiles`.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Lockfile {
+    releases: BTreeMap<String, String>,
+}
+
+/// A lockfile named on the command line, read as the arguments are
+/// parsed.
+#[derive(Debug, Default)]
+pub struct LockfileArg {
+    pub path: PathBuf,
+    /// The lockfile, if it exists.
+    pub lock: Option<Lockfile>,
+}
+
+impl LockfileArg {
+    /// Read the lockfile at `path`, as an argument parser.
+    pub fn read(path: &OsStr) -> Result<LockfileArg, OsString> {
+        let path = PathBuf::from(path);
+        let lock = if path.exists() {
+            Some(Lockfile::read(&path).map_err(|e| OsString::from(e.to_string()))?)
+        } else {
+            None
+        };
+        Ok(LockfileArg { path, lock })
+    }
+}
+
+/// A file whose version is not the one in the lockfile.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Drift {
+    pub file: String,
+    /// Version in the lockfile, if the file was recorded.
+    pub locked: Option<String>,
+    pub found: String,
+}
+
+impl fmt::Display for Drift {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.locked {
+            Some(ref locked) => write!(f, "{} is {}, locked at {}", self.file, self.found, locked),
+            None => write!(f, "{} {} is not in the lockfile", self.file, self.found),
+        }
+    }
+}
+
+impl Lockfile {
+    /// Read a lockfile. A lockfile that does not exist yet is empty.
+    pub fn read(path: &Path) -> Result<Lockfile, Error> {
+        let contents = match fs::read_to_string(path) {
+            Ok(contents) => contents,
+            Err(ref e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Lockfile::default()),
+            Err(e) => return Err(err_msg(format!("Could not read {}: {}", path.display(), e))),
+        };
+        let mut releases = BTreeMap::new();
+        for (n, line) in contents.lines().enumerate() {
+            if line.starts_with('#') || line.trim().is_empty() {
+                continue;
+            }
+            match line.split_once('\t') {
+                Some((file, release)) if !file.is_empty() && !release.is_empty() => {
+                    releases.insert(file.to_owned(), release.to_owned());
+                }
+                _ => {
+                    return Err(err_msg(format!(
+                        "{}:{}: expected a file name and a release separated by a tab",
+                        path.display(),
+                        n + 1
+                    )))
+                }
+            }
+        }
+        Ok(Lockfile { releases })
+    }
+
+    pub fn write(&self, path: &Path) -> Result<(), Error> {
+        let mut contents = format!("{}\n", HEADER);
+        for (file, release) in &self.releases {
+            contents.push_str(&format!("{}\t{}\n", file, release));
+        }
+        fs::write(path, contents)
+            .map_err(|e| err_msg(format!("Could not write {}: {}", path.display(), e)))
+    }
+
+    /// Record the versions of the files used in `report`, keeping
+    /// those of other documents.
+    pub fn update(&mut self, report: &BuildReport) {
+        for (file, release) in releases(&report.packages) {
+            self.releases.insert(file, release);
+        }
+    }
+
+    /// Files used in `report` whose versions differ from the
+    /// lockfile, or that are not in it.
+    pub fn drift(&self, report: &BuildReport) -> Vec<Drift> {
+        releases(&report.packages)
+            .filter(|(file, release)| self.releases.get(file) != Some(release))
+            .map(|(file, found)| Drift {
+                locked: self.releases.get(&file).cloned(),
+                file,
+                found,
+            })
+            .collect()
+    }
+}
+
+/// Files that declare a date or version. Files without one, such as
+/// graphics and the sources of the document, are not locked.
+fn releases(packages: &[PackageInfo]) -> impl Iterator<Item = (String, String)> + '_ {
+    packages
+        .iter()
+        .filter_map(|p| p.release().map(|r| (p.file.clone(), r)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn report(list: &str) -> BuildReport {
+        let log = format!(" *File List*\n{} ***********\n", list);
+        outparse::parse_log(log.as_bytes())
+    }
+
+    #[test]
+    fn test_drift() {
+        let dir = std::env::temp_dir().join(format!("rustex-lockfile-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join(LOCKFILE_NAME);
+
+        let mut lock = Lockfile::read(&path).unwrap();
+        lock.update(&report(
+            " article.cls    2019/10/25 v1.4k Standard LaTeX document class\ngraphicx.sty    2017/06/01 v1.1a Enhanced LaTeX Graphics\n",
+        ));
+        lock.write(&path).unwrap();
+        let lock = Lockfile::read(&path).unwrap();
+        let corrupt = dir.join("corrupt.lock");
+        fs::write(&corrupt, "article.cls 2019/10/25 v1.4k\n").unwrap();
+        assert!(Lockfile::read(&corrupt).is_err());
+        assert!(LockfileArg::read(corrupt.as_os_str()).is_err());
+        assert!(LockfileArg::read(dir.join("missing.lock").as_os_str()).unwrap().lock.is_none());
+
+        let drift = lock.drift(&report(
+            " article.cls    2019/10/25 v1.4k Standard LaTeX document class\ngraphicx.sty    2019/11/30 v1.2a Enhanced LaTeX Graphics\n  xcolor.sty    2016/05/11 v2.12 LaTeX color extensions\n figure.png\n",
+        ));
+
+        assert_eq!(
+            drift,
+            vec![
+                Drift {
+                    file: String::from("graphicx.sty"),
+                    locked: Some(String::from("2017/06/01 v1.1a")),
+                    found: String::from("2019/11/30 v1.2a"),
+                },
+                Drift {
+                    file: String::from("xcolor.sty"),
+                    locked: None,
+                    found: String::from("2016/05/11 v2.12"),
+                },
+            ]
+        );
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,337 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+
+use lazy_static::lazy_static;
+use regex::Regex;
+
+use outparse::{BuildReport, Message, MessageInfo};
+
+use crate::dependencies::{source_files, strip_comment};
+
+lazy_static! {
+    static ref MISSING_FILE: Regex = Regex::new(
+        r#"(?:File `([^']+)' not found|I can't find file `([^']+)')"#
+    ).unwrap();
+
+    static ref GRAPHICSPATH: Regex = Regex::new(r#"\\graphicspath\s*\{((?:\{[^}]*\}\s*)*)\}"#).unwrap();
+
+    static ref GRAPHICSPATH_ENTRY: Regex = Regex::new(r#"\{([^}]*)\}"#).unwrap();
+}
+
+/// Directories below the project searched for near matches.
+const MAX_DEPTH: usize = 5;
+
+/// Files looked at before the search gives up.
+const MAX_FILES: usize = 10000;
+
+/// Extensions tried by the graphics package, in the order it tries
+/// them.
+const GRAPHICS_EXTENSIONS: &[&str] = &["pdf", "png", "jpg", "jpeg", "eps", "ps"];
+
+/// TeX Live packages containing classes and packages whose names
+/// differ from the name of the file, or that are often missing from
+/// small installations.
+const TEXLIVE_PACKAGES: &[(&str, &str)] = &[
+    ("algorithm.sty", "algorithms"),
+    ("algorithmic.sty", "algorithms"),
+    ("algpseudocode.sty", "algorithmicx"),
+    ("amssymb.sty", "amsfonts"),
+    ("amsthm.sty", "amscls"),
+    ("array.sty", "tools"),
+    ("biblatex.sty", "biblatex"),
+    ("bm.sty", "tools"),
+    ("booktabs.sty", "booktabs"),
+    ("caption.sty", "caption"),
+    ("cleveref.sty", "cleveref"),
+    ("csquotes.sty", "csquotes"),
+    ("enumitem.sty", "enumitem"),
+    ("expl3.sty", "l3kernel"),
+    ("fancyhdr.sty", "fancyhdr"),
+    ("float.sty", "float"),
+    ("fontspec.sty", "fontspec"),
+    ("fullpage.sty", "preprint"),
+    ("geometry.sty", "geometry"),
+    ("graphicx.sty", "graphics"),
+    ("hyperref.sty", "hyperref"),
+    ("IEEEtran.cls", "ieeetran"),
+    ("lmodern.sty", "lm"),
+    ("listings.sty", "listings"),
+    ("longtable.sty", "tools"),
+    ("mathtools.sty", "mathtools"),
+    ("microtype.sty", "microtype"),
+    ("minted.sty", "minted"),
+    ("moderncv.cls", "moderncv"),
+    ("multicol.sty", "tools"),
+    ("multirow.sty", "multirow"),
+    ("natbib.sty", "natbib"),
+    ("pdfpages.sty", "pdfpages"),
+    ("pgfplots.sty", "pgfplots"),
+    ("siunitx.sty", "siunitx"),
+    ("soul.sty", "soul"),
+    ("standalone.cls", "standalone"),
+    ("subcaption.sty", "caption"),
+    ("subfig.sty", "subfig"),
+    ("tabularx.sty", "tools"),
+    ("tcolorbox.sty", "tcolorbox"),
+    ("tikz.sty", "pgf"),
+    ("todonotes.sty", "todonotes"),
+    ("ulem.sty", "ulem"),
+    ("url.sty", "url"),
+    ("verbatim.sty", "tools"),
+    ("wrapfig.sty", "wrapfig"),
+    ("xcolor.sty", "xcolor"),
+    ("xparse.sty", "l3packages"),
+    ("xspace.sty", "tools"),
+];
+
+/// A file of the project that is probably the one that was meant.
+#[derive(Debug, PartialEq)]
+enum NearMatch {
+    /// The name differs only in case.
+    Case(PathBuf),
+    /// The name has another extension.
+    Extension(PathBuf),
+    /// The file is in another directory.
+    Directory(PathBuf),
+}
+
+/// Files of the project, and the directories `\graphicspath` adds.
+struct Project {
+    root: PathBuf,
+    files: Vec<PathBuf>,
+}
+
+impl Project {
+    fn scan(document: &Path) -> Project {
+        let root = match document.parent() {
+            Some(dir) if dir != Path::new("") => dir.to_owned(),
+            _ => PathBuf::from("."),
+        };
+        let mut files = Vec::new();
+        walk(&root, 0, &mut files);
+        for dir in graphics_paths(document) {
+            let dir = root.join(dir);
+            if !dir.starts_with(&root) || dir.components().any(|c| c.as_os_str() == "..") {
+                walk(&dir, MAX_DEPTH - 1, &mut files);
+            }
+        }
+        Project { root, files }
+    }
+
+    /// Files that are probably the one named `wanted`.
+    fn near_matches(&self, wanted: &str) -> Vec<NearMatch> {
+        let wanted_path = Path::new(wanted);
+        let name = match wanted_path.file_name() {
+            Some(name) => name.to_string_lossy().to_lowercase(),
+            None => return Vec::new(),
+        };
+        let stem = wanted_path
+            .file_stem()
+            .map(|s| s.to_string_lossy().to_lowercase())
+            .unwrap_or_default();
+
+        let mut matches = Vec::new();
+        for file in &self.files {
+            let relative = file.strip_prefix(&self.root).unwrap_or(file);
+            if relative == wanted_path {
+                continue;
+            }
+            let file_name = match relative.file_name() {
+                Some(n) => n.to_string_lossy(),
+                None => continue,
+            };
+            let file_stem = relative
+                .file_stem()
+                .map(|s| s.to_string_lossy().to_lowercase())
+                .unwrap_or_default();
+            if file_name.to_lowercase() == name {
+                if relative.to_string_lossy().to_lowercase() == wanted.to_lowercase() {
+                    matches.push(NearMatch::Case(relative.to_owned()));
+                } else {
+                    matches.push(NearMatch::Directory(relative.to_owned()));
+                }
+            } else if file_stem == stem && relative.parent() == wanted_path.parent() {
+                matches.push(NearMatch::Extension(relative.to_owned()));
+            }
+        }
+        matches
+    }
+}
+
+/// Collect the files below `dir`, leaving out hidden directories.
+fn walk(dir: &Path, depth: usize, files: &mut Vec<PathBuf>) {
+    if depth > MAX_DEPTH || files.len() >= MAX_FILES {
+        return;
+    }
+    let entries = match fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(_) => return,
+    };
+    for entry in entries.filter_map(|e| e.ok()) {
+        let path = entry.path();
+        if entry.file_name().to_string_lossy().starts_with('.') {
+            continue;
+        }
+        if path.is_dir() {
+            walk(&path, depth + 1, files);
+        } else if files.len() < MAX_FILES {
+            files.push(path);
+        }
+    }
+}
+
+/// Directories given to `\graphicspath` in the sources of the
+/// document.
+fn graphics_paths(document: &Path) -> Vec<String> {
+    let mut dirs = Vec::new();
+    for file in source_files(document) {
+        let source = match fs::read_to_string(&file) {
+            Ok(source) => source,
+            Err(_) => continue,
+        };
+        let source: String = source.lines().map(strip_comment).collect();
+        for m in GRAPHICSPATH.captures_iter(&source) {
+            for entry in GRAPHICSPATH_ENTRY.captures_iter(&m[1]) {
+                dirs.push(entry[1].trim().to_owned());
+            }
+        }
+    }
+    dirs
+}
+
+/// Ask `kpsewhich` where the TeX distribution has a file.
+fn kpsewhich(name: &str) -> Option<PathBuf> {
+    let output = Command::new("kpsewhich")
+        .arg(name)
+        .stderr(Stdio::null())
+        .output()
+        .ok()?;
+    let found = String::from_utf8_lossy(&output.stdout).trim().to_owned();
+    if output.status.success() && !found.is_empty() {
+        Some(PathBuf::from(found))
+    } else {
+        None
+    }
+}
+
+fn is_package(name: &str) -> bool {
+    name.ends_with(".sty") || name.ends_with(".cls")
+}
+
+/// Suggest how to find the file `wanted`, using `find` to look it
+/// up in the TeX distribution.
+fn diagnose<F>(wanted: &str, project: &Project, find: F) -> Option<String>
+where
+    F: Fn(&str) -> Option<PathBuf>,
+{
+    let mut suggestions: Vec<String> = project
+        .near_matches(wanted)
+        .into_iter()
+        .map(|m| match m {
+            NearMatch::Case(path) => format!(
+                "{} differs only in case, which matters on most systems",
+                path.display()
+            ),
+            NearMatch::Extension(path) => format!("{} exists with another extension", path.display()),
+            NearMatch::Directory(path) => format!(
+                "{} exists in another directory; fix the path or add the directory to \\graphicspath",
+                path.display()
+            ),
+        })
+        .collect();
+
+    let is_graphic = Path::new(wanted)
+        .extension()
+        .is_some_and(|e| GRAPHICS_EXTENSIONS.contains(&e.to_string_lossy().to_lowercase().as_str()));
+    if !is_graphic {
+        if let Some(path) = find(wanted) {
+            suggestions.push(format!(
+                "the TeX distribution has it at {}, so the engine may be run with a different TEXINPUTS",
+                path.display()
+            ));
+        } else if is_package(wanted) {
+            suggestions.push(match TEXLIVE_PACKAGES.iter().find(|(file, _)| *file == wanted) {
+                Some((_, package)) => format!(
+                    "it is in the TeX Live package {}: install it with `tlmgr install {}'",
+                    package, package
+                ),
+                None => format!(
+                    "find the package providing it with `tlmgr search --global --file /{}'",
+                    wanted
+                ),
+            });
+        }
+    }
+
+    if suggestions.is_empty() {
+        None
+    } else {
+        Some(suggestions.join("; "))
+    }
+}
+
+fn missing_file(info: &MessageInfo) -> Option<String> {
+    let message = info.details.get("message")?;
+    let m = MISSING_FILE.captures(message)?;
+    m.get(1).or_else(|| m.get(2)).map(|f| f.as_str().to_owned())
+}
+
+/// Suggest fixes for the errors of `report` about files that could
+/// not be found, by looking for them around `document` and in the
+/// TeX distribution.
+pub fn annotate(report: &mut BuildReport, document: &Path) {
+    let mut project = None;
+    for message in &mut report.messages {
+        if let Message::Error(ref mut info) = message {
+            let wanted = match missing_file(info) {
+                Some(wanted) => wanted,
+                None => continue,
+            };
+            let project = project.get_or_insert_with(|| Project::scan(document));
+            if let Some(suggestion) = diagnose(&wanted, project, kpsewhich) {
+                info.details.insert(String::from("missing_file"), wanted);
+                info.details.insert(String::from("suggestion"), suggestion);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_diagnose_missing_files() {
+        let dir = std::env::temp_dir().join(format!("rustex-missing-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(dir.join("figures")).unwrap();
+        fs::create_dir_all(dir.join("images")).unwrap();
+        fs::write(dir.join("main.tex"), "\\graphicspath{{figures/}}\n").unwrap();
+        fs::write(dir.join("figures/Plot.png"), "").unwrap();
+        fs::write(dir.join("figures/diagram.pdf"), "").unwrap();
+        fs::write(dir.join("images/photo.jpg"), "").unwrap();
+        let project = Project::scan(&dir.join("main.tex"));
+        let nowhere = |_: &str| None;
+
+        assert_eq!(
+            project.near_matches("figures/plot.png"),
+            vec![NearMatch::Case(PathBuf::from("figures/Plot.png"))]
+        );
+        assert_eq!(
+            project.near_matches("figures/diagram.png"),
+            vec![NearMatch::Extension(PathBuf::from("figures/diagram.pdf"))]
+        );
+        assert!(diagnose("photo.jpg", &project, nowhere)
+            .unwrap()
+            .contains("images/photo.jpg exists in another directory"));
+        assert!(diagnose("tikz.sty", &project, nowhere)
+            .unwrap()
+            .contains("tlmgr install pgf"));
+        assert!(diagnose("article.cls", &project, |_| Some(PathBuf::from("/texmf/article.cls")))
+            .unwrap()
+            .contains("/texmf/article.cls"));
+        assert_eq!(diagnose("missing.png", &project, nowhere), None);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,77 @@
+use std::str::FromStr;
+
+/// Characters for bytes 0x80 to 0x9F in Windows-1252. Bytes that
+/// are not assigned are decoded as in Latin-1.
+const CP1252_HIGH: [char; 32] = [
+    '\u{20AC}', '\u{81}', '\u{201A}', '\u{192}', '\u{201E}', '\u{2026}', '\u{2020}', '\u{2021}',
+    '\u{2C6}', '\u{2030}', '\u{160}', '\u{2039}', '\u{152}', '\u{8D}', '\u{17D}', '\u{8F}',
+    '\u{90}', '\u{2018}', '\u{2019}', '\u{201C}', '\u{201D}', '\u{2022}', '\u{2013}', '\u{2014}',
+    '\u{2DC}', '\u{2122}', '\u{161}', '\u{203A}', '\u{153}', '\u{9D}', '\u{17E}', '\u{178}',
+];
+
+/// How the bytes of a log are decoded.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Encoding {
+    /// UTF-8, replacing invalid sequences with U+FFFD.
+    #[default]
+    Utf8,
+    /// ISO-8859-1, where every byte is a character.
+    Latin1,
+    /// Windows-1252.
+    Cp1252,
+}
+
+impl FromStr for Encoding {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Encoding, String> {
+        match s.to_lowercase().as_str() {
+            "utf-8" | "utf8" => Ok(Encoding::Utf8),
+            "latin-1" | "latin1" | "iso-8859-1" => Ok(Encoding::Latin1),
+            "cp1252" | "windows-1252" => Ok(Encoding::Cp1252),
+            _ => Err(format!(
+                "Unrecognised encoding: {} (expected utf-8, latin-1 or cp1252)",
+                s
+            )),
+        }
+    }
+}
+
+impl Encoding {
+    /// Decode a line of the log. Also returns whether any bytes
+    /// could not be decoded, and were replaced.
+    pub fn decode(self, bytes: &[u8]) -> (String, bool) {
+        match self {
+            Encoding::Utf8 => match String::from_utf8_lossy(bytes) {
+                std::borrow::Cow::Borrowed(s) => (s.to_owned(), false),
+                std::borrow::Cow::Owned(s) => (s, true),
+            },
+            Encoding::Latin1 => (bytes.iter().map(|&b| b as char).collect(), false),
+            Encoding::Cp1252 => (
+                bytes
+                    .iter()
+                    .map(|&b| match b {
+                        0x80..=0x9F => CP1252_HIGH[(b - 0x80) as usize],
+                        _ => b as char,
+                    })
+                    .collect(),
+                false,
+            ),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_decode() {
+        let bytes = b"caf\xe9 \x93quoted\x94";
+
+        assert_eq!(Encoding::Utf8.decode(bytes), (String::from("caf\u{fffd} \u{fffd}quoted\u{fffd}"), true));
+        assert_eq!(Encoding::Latin1.decode(bytes).0, "caf\u{e9} \u{93}quoted\u{94}");
+        assert_eq!(Encoding::Cp1252.decode(bytes).0, "caf\u{e9} \u{201c}quoted\u{201d}");
+        assert_eq!(Encoding::Utf8.decode("café".as_bytes()), (String::from("café"), false));
+    }
+}
@@ -0,0 +1,395 @@
+use std::fmt;
+
+use lazy_static::lazy_static;
+use regex::Regex;
+
+/// What an error means, and what usually fixes it.
+#[derive(Debug)]
+pub struct Explanation {
+    /// Stable code of the error, such as `E002`.
+    pub code: &'static str,
+    /// Text of the error, as written in the log.
+    pub title: &'static str,
+    /// Matches the text of the error, without the leading `! ` or
+    /// `LaTeX Error: `.
+    pattern: &'static str,
+    /// One sentence on what went wrong.
+    pub summary: &'static str,
+    pub causes: &'static [&'static str],
+    pub fixes: &'static [&'static str],
+}
+
+impl fmt::Display for Explanation {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "{}: {}", self.code, self.title)?;
+        writeln!(f)?;
+        writeln!(f, "{}", self.summary)?;
+        writeln!(f)?;
+        writeln!(f, "Typical causes:")?;
+        for cause in self.causes {
+            writeln!(f, "  - {}", cause)?;
+        }
+        writeln!(f)?;
+        writeln!(f, "Fixes:")?;
+        for fix in self.fixes {
+            writeln!(f, "  - {}", fix)?;
+        }
+        Ok(())
+    }
+}
+
+/// Explanations of the errors that are recognised. Codes are never
+/// reused, so new entries are added at the end.
+pub const CATALOGUE: &[Explanation] = &[
+    Explanation {
+        code: "E001",
+        title: "Undefined control sequence",
+        pattern: r#"^Undefined control sequence"#,
+        summary: "TeX read a command that has not been defined. The last command on the first context line is the undefined one.",
+        causes: &[
+            "A typo in the name of a command, such as \\bgein or \\textbf spelled \\textbold.",
+            "A command from a package that is not loaded.",
+            "A command used in the preamble before the package that defines it is loaded.",
+        ],
+        fixes: &[
+            "Check the spelling of the command at the end of the first `l.NN' line.",
+            "Load the package that defines it with \\usepackage.",
+        ],
+    },
+    Explanation {
+        code: "E002",
+        title: "Missing $ inserted",
+        pattern: r#"^Missing \$ inserted"#,
+        summary: "TeX found something that only works in math mode outside of it, or a blank line inside math.",
+        causes: &[
+            "Using _ or ^ in text, for example in a file name or identifier.",
+            "Using a math command such as \\alpha or \\times in text.",
+            "A blank line, or a forgotten closing $, inside an equation.",
+        ],
+        fixes: &[
+            "Wrap the math in $...$, or write \\_ and \\^{} for the characters themselves.",
+            "Remove blank lines from display math, and check that every $ is closed.",
+        ],
+    },
+    Explanation {
+        code: "E003",
+        title: "Too many }'s",
+        pattern: r#"^Too many \}'s"#,
+        summary: "A closing brace was found that does not close any group.",
+        causes: &[
+            "An extra } after a command argument.",
+            "A { that was removed or commented out, leaving its } behind.",
+        ],
+        fixes: &["Remove the extra } or add the missing { on the line shown."],
+    },
+    Explanation {
+        code: "E004",
+        title: "Misplaced alignment tab character &",
+        pattern: r#"^Misplaced alignment tab character &"#,
+        summary: "An & was used outside of a table or alignment, where it has no meaning.",
+        causes: &[
+            "An ampersand in running text, such as a company name.",
+            "An align or tabular environment that was closed too early.",
+        ],
+        fixes: &["Write \\& for a literal ampersand, or move the & inside the alignment."],
+    },
+    Explanation {
+        code: "E005",
+        title: "Extra alignment tab has been changed to \\cr",
+        pattern: r#"^Extra alignment tab has been changed to"#,
+        summary: "A row of a table has more cells than the table has columns.",
+        causes: &[
+            "An extra & in a row.",
+            "A missing \\\\ at the end of the previous row.",
+            "A column specification with fewer columns than intended.",
+        ],
+        fixes: &["Count the & on the row shown against the columns given to \\begin{tabular}."],
+    },
+    Explanation {
+        code: "E006",
+        title: "Missing } inserted",
+        pattern: r#"^Missing \} inserted"#,
+        summary: "A group or math formula ended before all of its braces were closed.",
+        causes: &["A { without a matching }, often inside math or a command argument."],
+        fixes: &["Find the unclosed { before the line shown and close it."],
+    },
+    Explanation {
+        code: "E007",
+        title: "Missing { inserted",
+        pattern: r#"^Missing \{ inserted"#,
+        summary: "TeX needed an opening brace, usually because math mode ended in the middle of something.",
+        causes: &[
+            "A $ that ends math mode inside a subscript or superscript.",
+            "A command whose argument must be enclosed in braces.",
+        ],
+        fixes: &["Check the $ signs and braces around subscripts and superscripts on the line shown."],
+    },
+    Explanation {
+        code: "E008",
+        title: "Extra }, or forgotten $",
+        pattern: r#"^Extra \}, or forgotten"#,
+        summary: "A closing brace was found where TeX expected math mode or a group to end first.",
+        causes: &[
+            "Braces that do not match inside a formula.",
+            "A formula opened with $ or \\( inside a group and closed outside it.",
+        ],
+        fixes: &["Make sure each formula starts and ends inside the same group."],
+    },
+    Explanation {
+        code: "E009",
+        title: "Missing \\begin{document}",
+        pattern: r#"^Missing \\begin\{document\}"#,
+        summary: "Something was typeset in the preamble, before \\begin{document}.",
+        causes: &[
+            "Text or a stray character in the preamble.",
+            "A package option or command with an argument that was not closed.",
+            "A file with a byte order mark or other invisible characters at the start.",
+        ],
+        fixes: &[
+            "Look at the line shown for text that belongs in the document body.",
+            "Check that the file starts with \\documentclass.",
+        ],
+    },
+    Explanation {
+        code: "E010",
+        title: "Environment undefined",
+        pattern: r#"^Environment \S+ undefined"#,
+        summary: "\\begin was given the name of an environment that does not exist.",
+        causes: &[
+            "A typo in the name of the environment.",
+            "An environment from a package that is not loaded, such as align from amsmath.",
+        ],
+        fixes: &["Correct the name, or load the package that defines the environment."],
+    },
+    Explanation {
+        code: "E011",
+        title: "\\begin ended by \\end",
+        pattern: r#"^\\begin\{[^}]+\} on input line \d+ ended by \\end"#,
+        summary: "An environment was closed with the \\end of another one.",
+        causes: &[
+            "A missing \\end for an inner environment.",
+            "A typo in the name given to \\end.",
+        ],
+        fixes: &["Add the missing \\end before the one shown, at the line where the environment should end."],
+    },
+    Explanation {
+        code: "E012",
+        title: "There's no line here to end",
+        pattern: r#"^There's no line here to end"#,
+        summary: "\\\\ or \\newline was used where no line has been started.",
+        causes: &[
+            "\\\\ at the start of a paragraph, or after a blank line.",
+            "\\\\ used to add vertical space between paragraphs.",
+        ],
+        fixes: &["Remove the \\\\, and use \\vspace or \\medskip for vertical space."],
+    },
+    Explanation {
+        code: "E013",
+        title: "Missing number, treated as zero",
+        pattern: r#"^Missing number, treated as zero"#,
+        summary: "TeX expected a number or a length but found something else.",
+        causes: &[
+            "A length argument such as width= that is empty or misspelled.",
+            "A \\\\ followed by [ on the next line, which is read as an optional spacing argument.",
+            "A counter name that is not defined.",
+        ],
+        fixes: &["Check the arguments that take numbers or lengths on the line shown."],
+    },
+    Explanation {
+        code: "E014",
+        title: "Illegal unit of measure (pt inserted)",
+        pattern: r#"^Illegal unit of measure"#,
+        summary: "A length was given without a unit, or with a unit TeX does not know.",
+        causes: &["A length such as width=5 instead of width=5cm.", "A misspelled unit."],
+        fixes: &["Add a unit such as pt, mm, cm, in, em or \\textwidth."],
+    },
+    Explanation {
+        code: "E015",
+        title: "Paragraph ended before a command was complete",
+        pattern: r#"^Paragraph ended before \S+ was complete"#,
+        summary: "A blank line, or \\par, was found inside the argument of a command that does not accept paragraphs.",
+        causes: &["A missing } in the argument of the command named.", "A blank line inside a short argument."],
+        fixes: &["Close the argument before the blank line, or remove the blank line."],
+    },
+    Explanation {
+        code: "E016",
+        title: "File not found",
+        pattern: r#"^File `[^']+' not found"#,
+        summary: "A file that was asked for could not be found in the project or the TeX distribution.",
+        causes: &[
+            "A typo or a difference in case in the name of the file.",
+            "A graphic in a directory not listed in \\graphicspath.",
+            "A package that is not installed.",
+        ],
+        fixes: &[
+            "Check the suggestion shown with the error, if any.",
+            "Install missing packages with the package manager of the TeX distribution.",
+        ],
+    },
+    Explanation {
+        code: "E017",
+        title: "Display math should end with $$",
+        pattern: r#"^Display math should end with \$\$"#,
+        summary: "Display math started with $$ did not end with $$.",
+        causes: &["A single $ closing a formula opened with $$."],
+        fixes: &["Use \\[ and \\] for display math, which give clearer errors."],
+    },
+    Explanation {
+        code: "E018",
+        title: "Double superscript or subscript",
+        pattern: r#"^Double (?:superscript|subscript)"#,
+        summary: "Two superscripts or two subscripts were given to the same symbol.",
+        causes: &["x^2^3 or x_i_j, meant as a nested script.", "A prime followed by a superscript, as in f'^2."],
+        fixes: &["Group the scripts with braces, as in x^{2^3} or {f'}^2."],
+    },
+    Explanation {
+        code: "E019",
+        title: "Something's wrong--perhaps a missing \\item",
+        pattern: r#"^Something's wrong--perhaps a missing \\item"#,
+        summary: "Text was found in a list environment before its first \\item, or where LaTeX expected a paragraph.",
+        causes: &[
+            "Text directly after \\begin{itemize} or \\begin{enumerate}.",
+            "\\\\ used in vertical mode, for example after \\maketitle.",
+        ],
+        fixes: &["Start the list with \\item, and remove stray \\\\."],
+    },
+    Explanation {
+        code: "E020",
+        title: "Command already defined",
+        pattern: r#"^Command \S+ already defined"#,
+        summary: "\\newcommand or \\newenvironment was used for a name that already exists.",
+        causes: &["A name that is also defined by a loaded package.", "The same definition made twice."],
+        fixes: &["Choose another name, or use \\renewcommand if the command is meant to be replaced."],
+    },
+    Explanation {
+        code: "E021",
+        title: "Can be used only in preamble",
+        pattern: r#"^Can be used only in preamble"#,
+        summary: "A command that configures the document, such as \\usepackage, was used after \\begin{document}.",
+        causes: &["\\usepackage or \\documentclass inside the document body or an included file."],
+        fixes: &["Move the command to the preamble of the main file."],
+    },
+    Explanation {
+        code: "E022",
+        title: "Option clash for package",
+        pattern: r#"^Option clash for package"#,
+        summary: "A package was loaded twice with different options.",
+        causes: &["A package loaded by the class or another package, and then again with options."],
+        fixes: &[
+            "Pass the options with \\PassOptionsToPackage before \\documentclass.",
+            "Load the package once, with all of its options.",
+        ],
+    },
+    Explanation {
+        code: "E023",
+        title: "Missing \\endcsname inserted",
+        pattern: r#"^Missing \\endcsname inserted"#,
+        summary: "A command was used in a place that must only contain characters, such as a label or file name.",
+        causes: &["Formatting commands, or math, in the argument of \\label, \\ref or \\cite."],
+        fixes: &["Use plain characters in labels and keys."],
+    },
+    Explanation {
+        code: "E024",
+        title: "Emergency stop",
+        pattern: r#"^Emergency stop"#,
+        summary: "TeX could not continue, usually because it needed input from the terminal while running in batch or nonstop mode.",
+        causes: &[
+            "An earlier error, such as a missing file, that TeX could not recover from.",
+            "A document that ends without \\end{document}.",
+        ],
+        fixes: &["Fix the first error in the log; later errors are often caused by it."],
+    },
+    Explanation {
+        code: "E025",
+        title: "TeX capacity exceeded",
+        pattern: r#"^TeX capacity exceeded"#,
+        summary: "One of the fixed size tables of the engine is full.",
+        causes: &[
+            "A command that calls itself without end, which fills the input stack or main memory.",
+            "A very large document or picture that needs more memory than the defaults.",
+        ],
+        fixes: &[
+            "Look for recursive definitions if the input stack or grouping levels overflowed.",
+            "Raise the size in texmf.cnf, or build with lualatex, which allocates memory as needed.",
+        ],
+    },
+    Explanation {
+        code: "E026",
+        title: "Unicode character not set up for use with LaTeX",
+        pattern: r#"^Unicode character .* \(U\+[0-9A-F]+\)"#,
+        summary: "The source contains a character that the fonts in use cannot typeset with pdflatex.",
+        causes: &["Characters pasted from a PDF or word processor, such as ligatures or special spaces."],
+        fixes: &[
+            "Replace the character with its LaTeX equivalent, or declare it with \\newunicodechar.",
+            "Build with lualatex or xelatex and a Unicode font.",
+        ],
+    },
+    Explanation {
+        code: "E027",
+        title: "Extra \\else, \\fi or \\or",
+        pattern: r#"^Extra \\(?:else|fi|or)"#,
+        summary: "A conditional was closed or continued without being opened.",
+        causes: &["A missing \\if..., or an extra \\fi, in a definition."],
+        fixes: &["Match each \\if... with exactly one \\fi."],
+    },
+    Explanation {
+        code: "E028",
+        title: "File ended while scanning",
+        pattern: r#"^File ended while scanning"#,
+        summary: "A file ended in the middle of the argument or definition of a command.",
+        causes: &["A missing } in the argument of the command named, often far from the end of the file."],
+        fixes: &["Look for the unclosed argument starting at the runaway text shown with the error."],
+    },
+    Explanation {
+        code: "E029",
+        title: "Dimension too large",
+        pattern: r#"^Dimension too large"#,
+        summary: "A length is larger than TeX can represent, about 5.75 metres.",
+        causes: &["A graphic or plot scaled with a very large factor.", "Coordinates of a picture far from the origin."],
+        fixes: &["Scale the data before plotting, or use smaller units."],
+    },
+    Explanation {
+        code: "E030",
+        title: "Argument of a command has an extra }",
+        pattern: r#"^Argument of \S+ has an extra \}"#,
+        summary: "An argument was closed before it started, usually because a fragile command was used in a moving argument.",
+        causes: &["A fragile command in a caption or section title.", "A missing { after a command."],
+        fixes: &["Put \\protect before the fragile command, or check the braces around the argument."],
+    },
+];
+
+lazy_static! {
+    static ref PATTERNS: Vec<Regex> = CATALOGUE
+        .iter()
+        .map(|e| Regex::new(e.pattern).unwrap())
+        .collect();
+}
+
+/// The explanation for an error code, ignoring case.
+pub fn explain(code: &str) -> Option<&'static Explanation> {
+    CATALOGUE.iter().find(|e| e.code.eq_ignore_ascii_case(code))
+}
+
+/// The explanation for the text of an error.
+pub fn explain_message(message: &str) -> Option<&'static Explanation> {
+    PATTERNS
+        .iter()
+        .position(|p| p.is_match(message))
+        .map(|i| &CATALOGUE[i])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_catalogue() {
+        for (i, explanation) in CATALOGUE.iter().enumerate() {
+            assert_eq!(explanation.code, format!("E{:03}", i + 1));
+        }
+        assert_eq!(explain("e002").unwrap().title, "Missing $ inserted");
+        assert_eq!(explain_message("Missing $ inserted.").unwrap().code, "E002");
+        assert_eq!(explain_message("Environment algn undefined.").unwrap().code, "E010");
+        assert!(explain_message("Something else.").is_none());
+    }
+}
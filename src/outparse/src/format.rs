@@ -0,0 +1,184 @@
+use std::fmt::Write;
+use std::str::FromStr;
+
+use crate::explain::explain;
+use crate::report::*;
+
+/// Output formats for build reports.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ReportFormat {
+    /// Summary followed by one line per message.
+    Human,
+    /// The full report serialised as JSON.
+    Json,
+    /// One `file:line: severity: message` line per message, as
+    /// understood by editors and CI annotations.
+    Gcc,
+}
+
+impl FromStr for ReportFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<ReportFormat, String> {
+        match s {
+            "human" => Ok(ReportFormat::Human),
+            "json" => Ok(ReportFormat::Json),
+            "gcc" => Ok(ReportFormat::Gcc),
+            _ => Err(format!(
+                "Unrecognised report format: {} (expected human, json or gcc)",
+                s
+            )),
+        }
+    }
+}
+
+/// Fraction of a table of the engine above which it is reported as
+/// nearly full.
+const NEARLY_FULL: f64 = 0.9;
+
+fn location(message: &Message, name: &str) -> String {
+    let file = message.file().unwrap_or(name);
+    let column = message
+        .as_ref()
+        .and_then(|m| m.details.get("byte_offset"))
+        .and_then(|o| o.parse::<usize>().ok());
+    match (message.line(), column) {
+        (Some(line), Some(offset)) => format!("{}:{}:{}", file, line, offset + 1),
+        (Some(line), None) => format!("{}:{}", file, line),
+        (None, _) => file.to_owned(),
+    }
+}
+
+fn format_human(report: &BuildReport, name: &str) -> String {
+    let mut out = format!("{}: {}\n", name, report);
+    if report.output.file.is_some() || report.output.engine.is_some() {
+        let _ = writeln!(out, "  Output: {}", report.output);
+    }
+    if let Some(ref hash) = report.output.sha256 {
+        let _ = writeln!(out, "  SHA-256: {}", hash);
+    }
+    if let Some(ref error) = report.output.error {
+        let _ = writeln!(out, "  Output not verified: {}", error);
+    }
+    if report.badboxes > 0 {
+        let _ = writeln!(out, "  Bad boxes: {}", report.badbox_counts);
+    }
+    if report.undecodable_lines > 0 {
+        let _ = writeln!(
+            out,
+            "  {} lines could not be decoded, try another encoding",
+            report.undecodable_lines
+        );
+    }
+    if !report.suppressed.is_empty() {
+        let _ = writeln!(out, "  Suppressed: {}", report.suppressed.len());
+    }
+    for message in &report.messages {
+        let mut place = location(message, name);
+        if let Some(page) = message.page() {
+            let _ = write!(place, " (page {})", page);
+        }
+        let _ = writeln!(
+            out,
+            "  {:<8}{}: {}",
+            message.severity(),
+            place,
+            message.to_str().trim_end()
+        );
+        if let Some(details) = message.as_ref().map(|m| &m.details) {
+            if let Some(snippet) = details.get("snippet") {
+                let _ = writeln!(out, "          {}", snippet);
+            }
+            if let Some(runaway) = details.get("runaway") {
+                let _ = writeln!(out, "          runaway text: {}", runaway);
+            }
+            if let Some(groups) = details.get("groups") {
+                let _ = writeln!(out, "          open: {}", groups);
+            } else if let Some(started) = details.get("started_line") {
+                let _ = writeln!(out, "          started at line {}", started);
+            }
+            if let Some(explanation) = details.get("code").and_then(|c| explain(c)) {
+                let _ = writeln!(out, "          {}: {}", explanation.code, explanation.summary);
+            }
+            if let Some(suggestion) = details.get("suggestion") {
+                let _ = writeln!(out, "          suggestion: {}", suggestion);
+            }
+        }
+    }
+    if let Some(ref exceeded) = report.resources.exceeded {
+        let _ = writeln!(out, "  Capacity exceeded: {}", exceeded);
+        let _ = writeln!(out, "  Fullest other tables:");
+        for limit in report.resources.fullest().iter().take(3) {
+            let _ = writeln!(out, "    {}", limit);
+        }
+    } else {
+        for limit in report.resources.fullest() {
+            if limit.fraction() >= NEARLY_FULL {
+                let _ = writeln!(out, "  Nearly full: {}", limit);
+            }
+        }
+    }
+    for (font, characters) in &report.fonts.missing_glyphs {
+        let _ = writeln!(out, "  Missing from {}: {}", font, characters.join(", "));
+    }
+    for substitution in &report.fonts.substitutions {
+        let _ = writeln!(
+            out,
+            "  Font {} replaced by {}",
+            substitution.requested, substitution.substituted
+        );
+    }
+    out
+}
+
+fn format_gcc(report: &BuildReport, name: &str) -> String {
+    let mut out = String::new();
+    for message in &report.messages {
+        let severity = match message.severity() {
+            Severity::Info => "note",
+            Severity::Warning => "warning",
+            Severity::Error => "error",
+        };
+        let _ = writeln!(
+            out,
+            "{}: {}: {}",
+            location(message, name),
+            severity,
+            message.to_str().trim_end()
+        );
+    }
+    out
+}
+
+/// Format a report for output. `name` identifies the log the report
+/// was read from, and is used for messages with no known file.
+pub fn format_report(report: &BuildReport, name: &str, format: ReportFormat) -> String {
+    match format {
+        ReportFormat::Human => format_human(report, name),
+        ReportFormat::Json => {
+            let mut out = serde_json::to_string_pretty(report).unwrap_or_default();
+            out.push('\n');
+            out
+        }
+        ReportFormat::Gcc => format_gcc(report, name),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::parse_log;
+
+    #[test]
+    fn test_gcc_format_uses_file_and_line() {
+        let log = "(./main.tex\nLaTeX Warning: Citation `knuth' on page 1 undefined on input line 7.\n(./chapter.tex\nUnderfull \\hbox (badness 10000) in paragraph at lines 9--10\n))\n";
+        let report = parse_log(log.as_bytes());
+
+        let out = format_report(&report, "main.log", ReportFormat::Gcc);
+
+        assert_eq!(
+            out,
+            "main.log: warning: Missing citation: knuth\nchapter.tex:9: warning: Underfull \\hbox (badness 10000) in paragraph at lines 9--10\n"
+        );
+    }
+}
@@ -1,8 +1,11 @@
-use lazy_static;
-use serde;
-
 pub mod report;
 pub use report::*;
 pub mod parser;
 pub use parser::*;
+pub mod encoding;
+pub use encoding::*;
+pub mod format;
+pub use format::*;
 
+pub mod explain;
+pub use explain::*;
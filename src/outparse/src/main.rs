@@ -0,0 +1,92 @@
+use std::fs::File;
+use std::io;
+use std::path::PathBuf;
+use std::process::exit;
+
+use structopt::StructOpt;
+
+use outparse::{
+    format_report, parse_log_encoded, BadboxThresholds, BuildReport, Encoding, ReportFormat,
+    Severity,
+};
+
+/// Analyse LaTeX log files.
+///
+/// Reads a log written by any LaTeX engine or build tool
+/// and prints the errors, warnings and bad boxes found in
+/// it.
+#[derive(StructOpt)]
+#[structopt(name = "outparse")]
+struct Options {
+    /// Output format: human, json or gcc.
+    #[structopt(long = "format", default_value = "human")]
+    format: ReportFormat,
+
+    /// Encoding of the log: utf-8, latin-1 or cp1252.
+    #[structopt(long = "encoding", default_value = "utf-8")]
+    encoding: Encoding,
+
+    /// Only show messages at least this severe: info,
+    /// warning or error.
+    #[structopt(long = "min-severity", default_value = "info")]
+    min_severity: Severity,
+
+    /// Only show messages from this package or class.
+    ///
+    /// Multiple calls show messages from any of the
+    /// packages given.
+    #[structopt(long = "package")]
+    packages: Vec<String>,
+
+    /// Hide overfull boxes that are overfull by less than
+    /// this many points.
+    #[structopt(long = "min-overfull", default_value = "0")]
+    min_overfull: f64,
+
+    /// Hide underfull boxes with a badness below this.
+    #[structopt(long = "min-badness", default_value = "0")]
+    min_badness: u32,
+
+    /// Log file to read. Reads standard input if not
+    /// given, or given as "-".
+    #[structopt(name = "log", parse(from_os_str))]
+    log: Option<PathBuf>,
+}
+
+fn read_report(log: &Option<PathBuf>, encoding: Encoding) -> io::Result<(BuildReport, String)> {
+    match log {
+        Some(path) if path.to_str() != Some("-") => {
+            let report = parse_log_encoded(File::open(path)?, encoding);
+            Ok((report, path.display().to_string()))
+        }
+        _ => Ok((parse_log_encoded(io::stdin(), encoding), String::from("<stdin>"))),
+    }
+}
+
+fn main() {
+    let options = Options::from_args();
+
+    let (mut report, name) = match read_report(&options.log, options.encoding) {
+        Ok(r) => r,
+        Err(e) => {
+            eprintln!("outparse: could not read log: {}", e);
+            exit(2);
+        }
+    };
+
+    report.filter_badboxes(&BadboxThresholds {
+        min_overfull: options.min_overfull,
+        min_badness: options.min_badness,
+    });
+
+    let min_severity = options.min_severity;
+    let packages = &options.packages;
+    report.retain_messages(|m| {
+        let package_matches = packages.is_empty()
+            || m.get_component_name()
+                .is_some_and(|c| packages.contains(c));
+        m.severity() >= min_severity && package_matches
+    });
+
+    print!("{}", format_report(&report, &name, options.format));
+}
@@ -1,86 +1,752 @@
 use std::collections::HashMap;
-use std::error::Error;
-use std::fmt;
 use std::io::prelude::*;
-use std::io::{self, BufReader};
+use std::io::BufReader;
 
 use lazy_static::lazy_static;
 use regex::{Captures, Regex};
-use serde::Serialize;
 
+use crate::encoding::Encoding;
+use crate::explain::explain_message;
 use crate::report::*;
 
-
-
 lazy_static! {
     static ref ERROR: Regex = Regex::new(
-        r#"^(?:! ((?:La|pdf)TeX|Package|Class)(?: (\w+))? [eE]rror(?: \(([\\]?\w+)\))?: (.*)|! (.*))"#
+        r#"^(?:! ((?:La|pdf|Lua|Xe)TeX3?|Package|Class|Module)(?: ([\w.\-]+))? [eE]rror(?: \(([\\]?\w+)\))?: (.*)|! (.*))"#
     ).unwrap();
 
     static ref WARNING: Regex = Regex::new(
-        r#"^((?:La|pdf)TeX|Package|Class)(?: (\w+))? [wW]arning(?: \(([\\]?\w+)\))?: (.*)"#
+        r#"^((?:La|pdf|Lua|Xe)TeX3?|Package|Class|Module)(?: ([\w.\-]+))? [wW]arning(?: \(([\\]?\w+)\))?: (.*)"#
     ).unwrap();
 
     static ref INFO: Regex = Regex::new(
-         r#"^((?:La|pdf)TeX|Package|Class)(?: (\w+))? [iI]nfo(?: \(([\\]?\w+)\))?: (.*)"#
+         r#"^((?:La|pdf|Lua|Xe)TeX3?|Package|Class|Module)(?: ([\w.\-]+))? [iI]nfo(?: \(([\\]?\w+)\))?: (.*)"#
     ).unwrap();
 
     static ref BADBOX: Regex = Regex::new(
         r#"^(Over|Under)full \\([hv])box \((?:badness (\d+)|(\d+(?:\.\d+)?pt) too \w+)\) (?:(?:(?:in paragraph|in alignment|detected) (?:at lines (\d+)--(\d+)|at line (\d+)))|(?:has occurred while [\\]output is active [\[](\d+)?[\]]))"#
     ).unwrap();
 
+    /// First line of the log, naming the engine.
+    static ref BANNER: Regex = Regex::new(
+        r#"^This is ([\w-]+), Version (\S+)(?: \(([^)]+)\))?"#
+    ).unwrap();
+
+    static ref DISTRIBUTION_YEAR: Regex = Regex::new(r#" (\d{4})$"#).unwrap();
+
+    static ref OUTPUT: Regex = Regex::new(
+        r#"^Output written on (.+) \((\d+) pages?(?:, (\d+) bytes)?\)\."#
+    ).unwrap();
+
+    static ref XDVIPDFMX: Regex = Regex::new(
+        r#"^xdvipdfmx:(warning|fatal): (.*)"#
+    ).unwrap();
+
+    static ref LUAOTFLOAD: Regex = Regex::new(
+        r#"^luaotfload \| (\w+) ?: (.*)"#
+    ).unwrap();
+
+    static ref MISSING_CHARACTER: Regex = Regex::new(
+        r#"^Missing character: There is no (.+?) (?:\(U\+([0-9A-Fa-f]+)\) )?in font (.+?)!"#
+    ).unwrap();
+
+    /// Errors written by the engine itself that have no fixed
+    /// layout, such as `! LuaTeX error [\directlua]:1: ...`.
+    static ref ENGINE_ERROR: Regex = Regex::new(
+        r#"^((?:pdf|Lua|Xe)TeX) error"#
+    ).unwrap();
+
+    /// Font identifiers and empty boxes in the contents of a bad box,
+    /// such as `[]\T1/lmr/m/n/10 (+20) `.
+    static ref BOX_MARKUP: Regex = Regex::new(
+        r#"\[\]|\\[A-Za-z0-9]+/\S*\s?(?:\([+-]?\d+\)\s?)?"#
+    ).unwrap();
+
+    static ref RUNAWAY: Regex = Regex::new(
+        r#"^Runaway (argument|definition|preamble|text)\?"#
+    ).unwrap();
+
+    static ref FILE_ENDED: Regex = Regex::new(
+        r#"^File ended while scanning (?:use|definition|preamble|text) of (\\[^\s.]+)"#
+    ).unwrap();
+
+    static ref PARAGRAPH_ENDED: Regex = Regex::new(
+        r#"^Paragraph ended before (\\\S+) was complete"#
+    ).unwrap();
+
+    static ref ENVIRONMENT_MISMATCH: Regex = Regex::new(
+        r#"\\begin\{([^}]+)\} on input line (\d+) ended by \\end\{([^}]+)\}"#
+    ).unwrap();
+
+    /// Written when the document ends with groups or conditionals
+    /// still open.
+    static ref UNCLOSED: Regex = Regex::new(
+        r#"^\(\\end occurred (?:inside a group at level (\d+)|when (\\\w+) on line (\d+) was incomplete)\)"#
+    ).unwrap();
+
+    static ref GROUP_LINE: Regex = Regex::new(
+        r#"^### (.+?) \(level (\d+)\) entered at line (\d+)"#
+    ).unwrap();
+
+    static ref MEMORY_HEADER: Regex = Regex::new(
+        r#"^Here is how much of \w+'s memory you used:"#
+    ).unwrap();
+
+    static ref MEMORY_LINE: Regex = Regex::new(
+        r#"^ (\d+) (strings|string characters|words of memory|multiletter control sequences|hyphenation exceptions) out of (\d+)(?:\+(\d+))?"#
+    ).unwrap();
+
+    static ref FONT_MEMORY_LINE: Regex = Regex::new(
+        r#"^ (\d+) words of font info for (\d+) fonts?, out of (\d+) for (\d+)"#
+    ).unwrap();
+
+    static ref STACK_LINE: Regex = Regex::new(
+        r#"^ (\d+)i,(\d+)n,(\d+)p,(\d+)b,(\d+)s stack positions out of (\d+)i,(\d+)n,(\d+)p,(\d+)b,(\d+)s"#
+    ).unwrap();
+
+    static ref CAPACITY_EXCEEDED: Regex = Regex::new(
+        r#"^TeX capacity exceeded, sorry \[(.+)=(\d+)\]"#
+    ).unwrap();
+
+    /// A line of the `*File List*` written by `\\listfiles`, with
+    /// the file name aligned to the right.
+    static ref FILE_LIST_ENTRY: Regex = Regex::new(
+        r#"^\s*(\S+)(?:\s+(?:(\d{4}[/-]\d{2}[/-]\d{2})\s*)?(?:(v\S+)\s*)?(.*))?$"#
+    ).unwrap();
+
+    static ref INPUT_LINE: Regex = Regex::new(
+        r#"on input line (\d+)"#
+    ).unwrap();
+
+    /// The line of the source where TeX stopped, shown after an error.
+    static ref CONTEXT_LINE: Regex = Regex::new(
+        r#"^l\.(\d+) "#
+    ).unwrap();
+
+    static ref FONT_SUBSTITUTION: Regex = Regex::new(
+        r#"^Font shape `([^']+)' (?:undefined|in size <([^>]+)> not available) (?:using `([^']+)' instead|size <([^>]+)> substituted)"#
+    ).unwrap();
+
+    static ref MULTIPLY_DEFINED: Regex = Regex::new(
+        r#"^Label `([^']+)' multiply defined"#
+    ).unwrap();
+
+    static ref DUPLICATE_DESTINATION: Regex = Regex::new(
+        r#"destination with the same identifier \(name\{([^}]+)\}\)"#
+    ).unwrap();
+
+    static ref DUPLICATE_ENTRY: Regex = Regex::new(
+        r#"Duplicate entry key '([^']+)'"#
+    ).unwrap();
+
     static ref MISSING_REFERENCE: Regex = Regex::new(
-        r#"^(Citation|Reference) `([^']+)' on page \d+ undefined on input line \d+."#
+        r#"^(Citation|Reference) `([^']+)' on page (\d+) undefined on input line \d+."#
     ).unwrap();
 }
 
+/// Events emitted while a log is being parsed, in the order in
+/// which they appear in the log.
+#[derive(Debug, Clone)]
+pub enum ParseEvent {
+    /// A message has been parsed in full.
+    Message(Message),
+    /// A page has been shipped out.
+    Page(usize),
+    /// The engine started reading a file.
+    FileOpened(String),
+    /// The engine finished reading a file. Carries the file that
+    /// is being read after it was closed, if any.
+    FileClosed(Option<String>),
+}
+
+/// Lines read before giving up on finding the context of an error.
+const MAX_PREAMBLE_LINES: usize = 10;
+
+/// Lines of help text kept for an error.
+const MAX_HELP_LINES: usize = 30;
+
+/// Tables of the engine: the name used in `TeX capacity exceeded`
+/// errors, the one used in the memory summary, and the `texmf.cnf`
+/// variable that sets its size.
+const RESOURCES: &[(&str, &str, Option<&str>)] = &[
+    ("number of strings", "strings", Some("max_strings")),
+    ("pool size", "string characters", Some("pool_size")),
+    ("main memory size", "words of memory", Some("main_memory")),
+    ("hash size", "multiletter control sequences", Some("hash_extra")),
+    ("exception dictionary", "hyphenation exceptions", Some("hyph_size")),
+    ("font memory", "words of font info", Some("font_mem_size")),
+    ("font max", "fonts", Some("font_max")),
+    ("input stack size", "i", Some("stack_size")),
+    ("semantic nest size", "n", Some("nest_size")),
+    ("parameter stack size", "p", Some("param_size")),
+    ("buffer size", "b", Some("buf_size")),
+    ("save size", "s", Some("save_size")),
+    ("grouping levels", "", None),
+    ("text input levels", "", None),
+];
+
+/// Describe the use of a table, named as in the memory summary or,
+/// failing that, as in `TeX capacity exceeded` errors.
+fn resource(name: &str, used: u64, limit: u64) -> ResourceLimit {
+    let known = RESOURCES
+        .iter()
+        .find(|(_, summary, _)| *summary == name)
+        .or_else(|| RESOURCES.iter().find(|(error, _, _)| *error == name));
+    ResourceLimit {
+        name: known.map_or(name, |(error, _, _)| error).to_owned(),
+        setting: known.and_then(|(_, _, setting)| setting.map(|s| s.to_owned())),
+        used,
+        limit,
+    }
+}
+
+/// Lines of the text of a runaway argument kept.
+const MAX_RUNAWAY_LINES: usize = 5;
+
+/// Lines the `Output written on` line may be wrapped over.
+const MAX_OUTPUT_LINES: usize = 3;
+
+/// Lines of box contents kept for a bad box.
+const MAX_SNIPPET_LINES: usize = 10;
+
+/// Parts of the block that TeX writes after an error message.
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum ErrorBlock {
+    /// Lines before the `l.NN` context, such as `See the LaTeX
+    /// manual...` or `For immediate help type H <return>.`, with the
+    /// number of lines seen so far.
+    Preamble(usize),
+    /// The line after `l.NN`, holding the part of the source line
+    /// that has not been read yet.
+    LineRest,
+    /// Help text, ended by a blank line, with the number of lines
+    /// seen so far.
+    Help(usize),
+}
+
 struct LogParser<'a, B: 'a + BufRead> {
     report: &'a mut BuildReport,
     reader: B,
     lineno: usize,
-    collect_remaining: usize,
     context_lines: usize,
+    /// Whether the last line was a message or one of its
+    /// continuation lines.
+    continuing: bool,
+    error: Option<ErrorBlock>,
+    /// Contents of the last bad box read so far, with the number of
+    /// lines they span.
+    snippet: Option<(String, usize)>,
+    /// Kind and text of a runaway argument, waiting for the error
+    /// that follows it, with the number of lines read.
+    runaway: Option<(String, String, usize)>,
+    /// Whether the last message lists the groups left open at the
+    /// end of the document.
+    groups: bool,
+    /// The `Output written on` line read so far, when the name of
+    /// the file is long enough for it to be wrapped, with the number
+    /// of lines read.
+    output: Option<(String, usize)>,
+    /// Whether the `*File List*` is being read.
+    file_list: bool,
+    /// Whether the memory summary at the end of the log is being read.
+    memory: bool,
+    /// Engine named in the banner at the start of the log.
+    engine: Option<String>,
+    encoding: Encoding,
+    files: Vec<Option<String>>,
+    page: Option<usize>,
+    /// Messages written since the last page was shipped out, whose
+    /// page was taken to be the one being built.
+    since_shipout: Vec<usize>,
+    emitted: usize,
+    observer: Box<dyn FnMut(ParseEvent) + 'a>,
 }
 
 impl<'a, B: 'a + BufRead> LogParser<'a, B> {
+    /// Read the next line as bytes, so that text which is not valid
+    /// in the encoding of the log does not end the parse early.
     fn next_line(&mut self) -> Option<String> {
-        let mut line = String::new();
-        match self.reader.read_line(&mut line) {
-            Ok(read) if read > 0 => {
-                self.lineno += 1;
-                Some(line)
+        let mut bytes = Vec::new();
+        loop {
+            match self.reader.read_until(b'\n', &mut bytes) {
+                Ok(0) if bytes.is_empty() => return None,
+                Ok(_) => break,
+                Err(ref e) if e.kind() == std::io::ErrorKind::Interrupted => continue,
+                Err(_) if !bytes.is_empty() => break,
+                Err(_) => return None,
             }
-            Ok(_) => None,
-            Err(_) => None,
         }
-    }
-
-    fn after_match(&mut self) {
-        self.collect_remaining = self.context_lines;
+        self.lineno += 1;
+        let (line, replaced) = self.encoding.decode(&bytes);
+        if replaced {
+            self.report.undecodable_lines += 1;
+        }
+        Some(line)
     }
 
     fn parse_line(&mut self, line: &str) {
-        if let Some(m) = INFO.captures(&line) {
+        // Messages start at the beginning of a line, but file and page
+        // markers can follow on the rest of it.
+        let end = |m: &Captures| m.get(0).unwrap().end();
+        let rest = if let Some(m) = INFO.captures(&line) {
+            let rest = end(&m);
             self.process_info(m);
-            //self.after_match();
+            rest
         } else if let Some(m) = BADBOX.captures(&line) {
+            let rest = end(&m);
             self.process_badbox(m);
-            //self.after_match();
+            rest
         } else if let Some(m) = WARNING.captures(&line) {
+            let rest = end(&m);
             self.process_warning(m);
-            //self.after_match();
+            rest
         } else if let Some(m) = ERROR.captures(&line) {
+            let rest = end(&m);
             self.process_error(m);
-            //self.after_match();
+            rest
+        } else if let Some(m) = MISSING_CHARACTER.captures(line) {
+            let rest = end(&m);
+            self.process_missing_character(m);
+            rest
+        } else if let Some(m) = XDVIPDFMX.captures(line) {
+            let rest = end(&m);
+            self.process_xdvipdfmx(m);
+            rest
+        } else if let Some(m) = LUAOTFLOAD.captures(line) {
+            let rest = end(&m);
+            self.process_luaotfload(m);
+            rest
+        } else if let Some(m) = RUNAWAY.captures(line) {
+            self.runaway = Some((m[1].to_owned(), String::new(), 0));
+            end(&m)
+        } else if let Some(m) = UNCLOSED.captures(line) {
+            let rest = end(&m);
+            self.process_unclosed(m);
+            rest
+        } else if let Some(m) = BANNER.captures(line) {
+            self.process_banner(m);
+            line.len()
+        } else if line.starts_with("Output written on") {
+            self.output = Some((String::new(), 0));
+            self.continue_output(line);
+            line.len()
+        } else if line.trim() == "*File List*" {
+            self.file_list = true;
+            line.len()
+        } else if MEMORY_HEADER.is_match(line) {
+            self.memory = true;
+            line.len()
+        } else {
+            0
+        };
+        self.scan_structure(&line[rest..]);
+    }
+
+    /// Follow the file and page markers that TeX writes in the log.
+    ///
+    /// An opening parenthesis followed by a file name marks the
+    /// start of reading that file, and the matching closing
+    /// parenthesis marks its end. Page shipouts appear as `[12]`,
+    /// possibly with included files or font maps before the
+    /// closing bracket.
+    fn scan_structure(&mut self, line: &str) {
+        let bytes = line.as_bytes();
+        let mut pos = 0;
+        while pos < bytes.len() {
+            match bytes[pos] {
+                b'(' => {
+                    let start = pos + 1;
+                    let end = line[start..]
+                        .find(|c: char| c.is_whitespace() || c == '(' || c == ')' || c == '[')
+                        .map_or(line.len(), |i| start + i);
+                    let name = &line[start..end];
+                    if is_file_name(name) {
+                        let name = name.trim_start_matches("./").to_owned();
+                        self.files.push(Some(name.clone()));
+                        (self.observer)(ParseEvent::FileOpened(name));
+                    } else {
+                        self.files.push(None);
+                    }
+                    pos = end;
+                    continue;
+                }
+                b')' => {
+                    if let Some(Some(_)) = self.files.pop() {
+                        let current = self.current_file().map(|f| f.to_owned());
+                        (self.observer)(ParseEvent::FileClosed(current));
+                    }
+                }
+                b'[' => {
+                    let start = pos + 1;
+                    let end = line[start..]
+                        .find(|c: char| !c.is_ascii_digit())
+                        .map_or(line.len(), |i| start + i);
+                    let terminated = match bytes.get(end) {
+                        None => true,
+                        Some(c) => b"] <{".contains(c),
+                    };
+                    if end > start && terminated {
+                        if let Ok(page) = line[start..end].parse() {
+                            self.page = Some(page);
+                            self.since_shipout.clear();
+                            (self.observer)(ParseEvent::Page(page));
+                        }
+                    }
+                    pos = end;
+                    continue;
+                }
+                _ => {}
+            }
+            pos += 1;
         }
     }
 
-    fn process_generic(&mut self, m: Captures) -> MessageInfo {
+    /// Give the messages from `start` on the page being built, which
+    /// follows the last one shipped out, unless TeX named their page.
+    fn estimate_pages(&mut self, start: usize) {
+        let page = self.page.map_or(1, |p| p + 1);
+        for index in start..self.report.messages.len() {
+            if self.report.messages[index].estimate_page(page) {
+                self.since_shipout.push(index);
+            }
+        }
+    }
+
+    /// Add a continuation line such as `(fontspec)  more text` to
+    /// the last message.
+    fn continue_message(&mut self, line: &str) -> bool {
+        if !self.continuing {
+            return false;
+        }
+        let last = match self.report.messages.last_mut() {
+            Some(last) => last,
+            None => return false,
+        };
+        let prefix = match last.continuation_prefix() {
+            Some(prefix) if line.starts_with(&prefix) => prefix,
+            _ => {
+                self.continuing = false;
+                return false;
+            }
+        };
+
+        let text = line[prefix.len()..].trim();
+        if !text.is_empty() {
+            last.extend_message(text);
+        }
+        if let Some(info) = last.as_mut() {
+            set_input_line(info);
+        }
+        true
+    }
+
+    /// Collect the context and help text that follow an error.
+    ///
+    /// TeX writes the error message, some lines asking for input,
+    /// the context of the error starting with `l.NN` (the line of
+    /// the source that was being read), the rest of that line, and
+    /// finally the help text up to a blank line.
+    fn continue_error(&mut self, line: &str) -> bool {
+        let stage = match self.error {
+            Some(stage) => stage,
+            None => return false,
+        };
+        let gave_up = match stage {
+            ErrorBlock::Preamble(seen) => seen >= MAX_PREAMBLE_LINES,
+            ErrorBlock::Help(seen) => seen >= MAX_HELP_LINES,
+            ErrorBlock::LineRest => false,
+        };
+        if gave_up
+            || line.starts_with("! ")
+            || MEMORY_HEADER.is_match(line)
+            || INFO.is_match(line)
+            || WARNING.is_match(line)
+            || BADBOX.is_match(line)
+        {
+            self.error = None;
+            return false;
+        }
+
+        let trimmed = line.trim();
+        let next = match stage {
+            ErrorBlock::Preamble(_) if CONTEXT_LINE.is_match(line) => {
+                let lineno = CONTEXT_LINE.captures(line).unwrap()[1].to_owned();
+                if let Some(info) = self.last_info() {
+                    info.details.entry(String::from("line")).or_insert(lineno);
+                }
+                self.add_context(line.trim_end());
+                Some(ErrorBlock::LineRest)
+            }
+            // LaTeX indents the rest of a long error message.
+            ErrorBlock::Preamble(0) if line.starts_with(' ') && !trimmed.is_empty() => {
+                if let Some(last) = self.report.messages.last_mut() {
+                    last.extend_message(trimmed);
+                    if let Some(info) = last.as_mut() {
+                        set_code(info);
+                    }
+                }
+                Some(ErrorBlock::Preamble(1))
+            }
+            ErrorBlock::Preamble(seen) => {
+                if line.starts_with('<') {
+                    self.add_context(line.trim_end());
+                } else if !is_context_line(line) {
+                    self.scan_structure(line);
+                }
+                Some(ErrorBlock::Preamble(seen + 1))
+            }
+            ErrorBlock::LineRest => {
+                if !trimmed.is_empty() {
+                    self.add_context(trimmed);
+                }
+                Some(ErrorBlock::Help(0))
+            }
+            ErrorBlock::Help(_) if trimmed.is_empty() => None,
+            ErrorBlock::Help(seen) => {
+                if let Some(last) = self.report.messages.last_mut() {
+                    last.add_help(trimmed);
+                }
+                Some(ErrorBlock::Help(seen + 1))
+            }
+        };
+
+        self.error = next;
+        true
+    }
+
+    /// Collect the contents of a horizontal bad box, which TeX shows
+    /// on the lines after the message up to a blank line.
+    fn continue_snippet(&mut self, line: &str) -> bool {
+        let seen = match self.snippet {
+            Some((_, seen)) => seen,
+            None => return false,
+        };
+        if line.trim().is_empty() {
+            self.end_snippet();
+            return true;
+        }
+        if seen >= MAX_SNIPPET_LINES
+            || line.starts_with("! ")
+            || INFO.is_match(line)
+            || WARNING.is_match(line)
+            || BADBOX.is_match(line)
+        {
+            self.end_snippet();
+            return false;
+        }
+        if let Some((ref mut text, ref mut seen)) = self.snippet {
+            // Long lines are broken by TeX without adding a space.
+            text.push_str(line);
+            *seen += 1;
+        }
+        true
+    }
+
+    /// Collect the text TeX shows after `Runaway argument?`, up to
+    /// the error that explains it.
+    fn continue_runaway(&mut self, line: &str) -> bool {
+        match self.runaway {
+            Some(_) if line.starts_with("! ") => false,
+            Some((_, ref mut text, ref mut seen)) if *seen < MAX_RUNAWAY_LINES => {
+                text.push_str(line);
+                *seen += 1;
+                true
+            }
+            Some(_) => {
+                self.runaway = None;
+                false
+            }
+            None => false,
+        }
+    }
+
+    /// Read the `### simple group (level 1) entered at line 42 ({)`
+    /// lines after an unclosed group, ended by `### bottom level`.
+    fn continue_groups(&mut self, line: &str) -> bool {
+        if !self.groups {
+            return false;
+        }
+        if line.trim().is_empty() {
+            return true;
+        }
+        if !line.starts_with("### ") {
+            self.groups = false;
+            return false;
+        }
+        if let (Some(m), Some(info)) = (GROUP_LINE.captures(line), self.last_info()) {
+            let group = format!("{} (level {}) at line {}", &m[1], &m[2], &m[3]);
+            let groups = info.details.entry(String::from("groups")).or_default();
+            if !groups.is_empty() {
+                groups.push_str("; ");
+            }
+            groups.push_str(&group);
+            // Groups are listed from the innermost one out, which is
+            // the one that was left open.
+            if !info.details.contains_key("started_line") {
+                info.details
+                    .insert(String::from("started_line"), m[3].to_owned());
+                info.details.insert(String::from("line"), m[3].to_owned());
+            }
+        } else if line.starts_with("### bottom level") {
+            self.groups = false;
+        }
+        true
+    }
+
+    fn process_banner(&mut self, m: Captures) {
+        self.engine = Some(m[1].to_owned());
+        let output = &mut self.report.output;
+        output.engine = Some(m[1].to_owned());
+        output.engine_version = Some(m[2].to_owned());
+        if let Some(distribution) = m.get(3) {
+            let distribution = distribution.as_str();
+            output.distribution = Some(distribution.to_owned());
+            output.year = DISTRIBUTION_YEAR
+                .captures(distribution)
+                .and_then(|y| y[1].parse().ok());
+        }
+    }
+
+    /// Read the `Output written on main.pdf (12 pages, 345678
+    /// bytes).` line, which may be wrapped.
+    fn continue_output(&mut self, line: &str) -> bool {
+        let (mut text, seen) = match self.output.take() {
+            Some(output) => output,
+            None => return false,
+        };
+        text.push_str(line);
+        if let Some(m) = OUTPUT.captures(&text) {
+            let output = &mut self.report.output;
+            output.file = Some(m[1].to_owned());
+            output.pages = m[2].parse().ok();
+            output.bytes = m.get(3).and_then(|b| b.as_str().parse().ok());
+        } else if seen + 1 < MAX_OUTPUT_LINES {
+            self.output = Some((text, seen + 1));
+        }
+        true
+    }
+
+    /// Read the files listed by `\\listfiles`, up to the line of
+    /// asterisks that ends the list.
+    fn continue_file_list(&mut self, line: &str) -> bool {
+        if !self.file_list {
+            return false;
+        }
+        let trimmed = line.trim();
+        if trimmed.starts_with("***") || trimmed.is_empty() {
+            self.file_list = false;
+            return true;
+        }
+        if let Some(m) = FILE_LIST_ENTRY.captures(line) {
+            let text = |i: usize| {
+                m.get(i)
+                    .map(|t| t.as_str().trim().to_owned())
+                    .filter(|t| !t.is_empty())
+            };
+            self.report.packages.push(PackageInfo {
+                file: m[1].to_owned(),
+                date: text(2),
+                version: text(3),
+                description: text(4),
+            });
+        }
+        true
+    }
+
+    /// Read the lines of the memory summary, which are indented by
+    /// a space.
+    fn continue_memory(&mut self, line: &str) -> bool {
+        if !self.memory {
+            return false;
+        }
+        if !line.starts_with(' ') {
+            self.memory = false;
+            return false;
+        }
+        let number = |m: &Captures, i: usize| m[i].parse::<u64>().unwrap_or(0);
+        let limits = &mut self.report.resources.limits;
+        if let Some(m) = MEMORY_LINE.captures(line) {
+            let limit = number(&m, 3) + m.get(4).map_or(0, |_| number(&m, 4));
+            limits.push(resource(&m[2], number(&m, 1), limit));
+        } else if let Some(m) = FONT_MEMORY_LINE.captures(line) {
+            limits.push(resource("words of font info", number(&m, 1), number(&m, 3)));
+            limits.push(resource("fonts", number(&m, 2), number(&m, 4)));
+        } else if let Some(m) = STACK_LINE.captures(line) {
+            for (i, stack) in ["i", "n", "p", "b", "s"].iter().enumerate() {
+                limits.push(resource(stack, number(&m, i + 1), number(&m, i + 6)));
+            }
+        }
+        true
+    }
+
+    fn end_snippet(&mut self) {
+        let text = match self.snippet.take() {
+            Some((text, _)) => text,
+            None => return,
+        };
+        let text = BOX_MARKUP.replace_all(&text, " ");
+        let text = text.split_whitespace().collect::<Vec<_>>().join(" ");
+        if let (false, Some(info)) = (text.is_empty(), self.last_info()) {
+            info.details.insert(String::from("snippet"), text);
+        }
+    }
+
+    fn last_info(&mut self) -> Option<&mut MessageInfo> {
+        self.report.messages.last_mut().and_then(|m| m.as_mut())
+    }
+
+    /// Add a line of context to the last message, keeping only the
+    /// lines closest to the error.
+    fn add_context(&mut self, line: &str) {
+        let limit = self.context_lines;
+        if let Some(last) = self.report.messages.last_mut() {
+            last.add_context(line.to_owned());
+            if let Some(info) = last.as_mut() {
+                let excess = info.context_lines.len().saturating_sub(limit);
+                info.context_lines.drain(..excess);
+            }
+        }
+    }
+
+    fn current_file(&self) -> Option<&str> {
+        self.files
+            .iter()
+            .rev()
+            .find_map(|f| f.as_ref())
+            .map(|f| f.as_str())
+    }
+
+    /// Pass any messages that have been completed to the observer.
+    ///
+    /// Messages are passed on as soon as they are complete, with the
+    /// page being built. The final report has no page for those
+    /// written after the last page was shipped out.
+    fn flush_messages(&mut self) {
+        while self.emitted < self.report.messages.len() {
+            if let Some(info) = self.report.messages[self.emitted].as_mut() {
+                set_font_substitution(info);
+            }
+            let message = self.report.messages[self.emitted].clone();
+            self.emitted += 1;
+            (self.observer)(ParseEvent::Message(message));
+        }
+    }
+
+    /// Create the info for a message found on the current line,
+    /// recording the file that was being read at the time.
+    fn new_info(&self, full: &str) -> MessageInfo {
         let mut info = MessageInfo {
-            full: m.get(0).unwrap().as_str().to_owned(),
+            full: full.to_owned(),
             details: HashMap::new(),
             context_lines: Vec::new(),
         };
+        if let Some(file) = self.current_file() {
+            info.details.insert(String::from("file"), file.to_owned());
+        }
+        info
+    }
+
+    fn process_generic(&mut self, m: Captures) -> MessageInfo {
+        let mut info = self.new_info(m.get(0).unwrap().as_str());
 
         // 0 - Whole match
         // 1 - Type ((?:La|pdf)TeX|Package|Class)
@@ -91,6 +757,10 @@ impl<'a, B: 'a + BufRead> LogParser<'a, B> {
         let type_name = m.get(1).unwrap().as_str();
         info.details
             .insert(String::from("type"), type_name.to_owned());
+        if let "pdfTeX" | "LuaTeX" | "XeTeX" = type_name {
+            info.details
+                .insert(String::from("engine"), type_name.to_owned());
+        }
         if let Some(name) = m.get(2) {
             let key = match type_name {
                 "Package" => String::from("package"),
@@ -120,11 +790,7 @@ impl<'a, B: 'a + BufRead> LogParser<'a, B> {
     }
 
     fn process_badbox(&mut self, m: Captures) {
-        let mut info = MessageInfo {
-            full: m.get(0).unwrap().as_str().to_owned(),
-            details: HashMap::new(),
-            context_lines: Vec::new(),
-        };
+        let mut info = self.new_info(m.get(0).unwrap().as_str());
 
         // Regex match groups
         // 0 - Whole match
@@ -175,54 +841,237 @@ impl<'a, B: 'a + BufRead> LogParser<'a, B> {
     }
 
 
-    fn process_missing_reference(&mut self, label: &str) {
+    fn process_missing_reference(&mut self, label: &str, page: Option<usize>) {
         self.report.missing_references += 1;
         self.report.messages.push(
-            Message::MissingReference {label: label.to_owned()}
+            Message::MissingReference {label: label.to_owned(), page}
         )
     }
 
-    fn process_missing_citation(&mut self, label: &str) {
+    fn process_missing_citation(&mut self, label: &str, page: Option<usize>) {
         self.report.missing_citations += 1;
         self.report.messages.push(
-            Message::MissingCitation {label: label.to_owned()}
+            Message::MissingCitation {label: label.to_owned(), page}
         )
     }
 
+    fn process_multiply_defined(&mut self, label: &str, kind: DuplicateKind) {
+        self.report.multiply_defined += 1;
+        self.report.messages.push(Message::MultiplyDefined {
+            label: label.to_owned(),
+            kind,
+            page: None,
+        })
+    }
+
+    /// Sort warnings about keys defined more than once into their own
+    /// category. The summary LaTeX writes at the end of the run only
+    /// repeats them, so it is kept as information.
+    fn process_duplicate(&mut self, info: MessageInfo) -> Option<MessageInfo> {
+        let message = match info.details.get("message") {
+            Some(message) => message.clone(),
+            None => return Some(info),
+        };
+        if let Some(m) = MULTIPLY_DEFINED.captures(&message) {
+            self.process_multiply_defined(&m[1], DuplicateKind::Label);
+        } else if let Some(m) = DUPLICATE_DESTINATION.captures(&message) {
+            self.process_multiply_defined(&m[1], DuplicateKind::Destination);
+        } else if let Some(m) = DUPLICATE_ENTRY.captures(&message) {
+            self.process_multiply_defined(&m[1], DuplicateKind::BibEntry);
+        } else if message.starts_with("There were multiply-defined labels") {
+            self.report.info += 1;
+            self.report.messages.push(Message::Info(info));
+        } else {
+            return Some(info);
+        }
+        None
+    }
+
     fn process_warning(&mut self, m: Captures) {
-        let info = self.process_generic(m);
+        let mut info = self.process_generic(m);
+        set_input_line(&mut info);
         if let Some(message) = info.details.get("message") {
             if let Some(m) = MISSING_REFERENCE.captures(message) {
                 // 0 - whole match
                 // 1 - type
                 // 2 - label
+                // 3 - page
                 let type_ = m.get(1).unwrap().as_str();
+                let page = m[3].parse().ok();
                 if type_ == "Citation" {
-                    self.process_missing_citation(m.get(2).unwrap().as_str());
+                    self.process_missing_citation(m.get(2).unwrap().as_str(), page);
                 } else if type_ == "Reference" {
-                    self.process_missing_reference(m.get(2).unwrap().as_str());
+                    self.process_missing_reference(m.get(2).unwrap().as_str(), page);
                 }
                 return
             }
         }
+        if let Some(info) = self.process_duplicate(info) {
+            self.report.warnings += 1;
+            self.report.messages.push(Message::Warning(info));
+        }
+    }
+
+    fn process_missing_character(&mut self, m: Captures) {
+        let mut info = self.new_info(m.get(0).unwrap().as_str());
+
+        // 0 - Whole match
+        // 1 - character
+        // 2 - code point, on newer engines (U\+([0-9A-F]+))?
+        // 3 - font
+
+        info.details
+            .insert(String::from("type"), String::from("Missing character"));
+        info.details
+            .insert(String::from("character"), m[1].to_owned());
+        if let Some(code) = m.get(2) {
+            info.details
+                .insert(String::from("codepoint"), code.as_str().to_uppercase());
+        }
+        info.details.insert(String::from("font"), m[3].to_owned());
+        if let Some(ref engine) = self.engine {
+            info.details.insert(String::from("engine"), engine.clone());
+        }
+        info.details.insert(
+            String::from("message"),
+            format!("There is no {} in font {}", &m[1], &m[3]),
+        );
+
         self.report.warnings += 1;
         self.report.messages.push(Message::Warning(info));
     }
 
+    fn process_xdvipdfmx(&mut self, m: Captures) {
+        let mut info = self.new_info(m.get(0).unwrap().as_str());
+        info.details
+            .insert(String::from("type"), String::from("xdvipdfmx"));
+        info.details
+            .insert(String::from("engine"), String::from("XeTeX"));
+        info.details
+            .insert(String::from("message"), m[2].to_owned());
+
+        if &m[1] == "fatal" {
+            self.report.errors += 1;
+            self.report.messages.push(Message::Error(info));
+        } else {
+            self.report.warnings += 1;
+            self.report.messages.push(Message::Warning(info));
+        }
+    }
+
+    /// luaotfload writes both progress reports and problems with the
+    /// same layout, so only the categories used while loading the
+    /// font database are treated as information.
+    fn process_luaotfload(&mut self, m: Captures) {
+        let mut info = self.new_info(m.get(0).unwrap().as_str());
+        info.details
+            .insert(String::from("type"), String::from("luaotfload"));
+        info.details
+            .insert(String::from("engine"), String::from("LuaTeX"));
+        info.details
+            .insert(String::from("category"), m[1].to_owned());
+        info.details
+            .insert(String::from("message"), m[2].to_owned());
+
+        if let "db" | "init" | "load" | "conf" = &m[1] {
+            self.report.info += 1;
+            self.report.messages.push(Message::Info(info));
+        } else {
+            self.report.warnings += 1;
+            self.report.messages.push(Message::Warning(info));
+        }
+    }
+
+    fn process_unclosed(&mut self, m: Captures) {
+        let mut info = self.new_info(m.get(0).unwrap().as_str());
+        info.details.insert(String::from("type"), String::from("TeX"));
+        let message = m[0].trim_start_matches('(').trim_end_matches(')');
+        info.details
+            .insert(String::from("message"), message.to_owned());
+
+        if let Some(level) = m.get(1) {
+            info.details
+                .insert(String::from("structure"), String::from("unclosed-group"));
+            info.details
+                .insert(String::from("level"), level.as_str().to_owned());
+            self.groups = true;
+        } else {
+            info.details.insert(
+                String::from("structure"),
+                String::from("unclosed-conditional"),
+            );
+            info.details
+                .insert(String::from("command"), m[2].to_owned());
+            info.details
+                .insert(String::from("started_line"), m[3].to_owned());
+            info.details.insert(String::from("line"), m[3].to_owned());
+        }
+
+        self.report.warnings += 1;
+        self.report.messages.push(Message::Warning(info));
+    }
+
+    /// Record where an error about the structure of the document
+    /// started, when TeX gives more than the place it noticed it.
+    fn set_structure(&mut self, info: &mut MessageInfo) {
+        let message = match info.details.get("message") {
+            Some(message) => message.clone(),
+            None => return,
+        };
+        let details = &mut info.details;
+        if let Some(m) = FILE_ENDED.captures(&message) {
+            details.insert(String::from("structure"), String::from("file-ended"));
+            details.insert(String::from("command"), m[1].to_owned());
+        } else if let Some(m) = PARAGRAPH_ENDED.captures(&message) {
+            details.insert(String::from("structure"), String::from("paragraph-ended"));
+            details.insert(String::from("command"), m[1].to_owned());
+        } else if let Some(m) = ENVIRONMENT_MISMATCH.captures(&message) {
+            details.insert(
+                String::from("structure"),
+                String::from("environment-mismatch"),
+            );
+            details.insert(String::from("environment"), m[1].to_owned());
+            details.insert(String::from("started_line"), m[2].to_owned());
+            details.insert(String::from("ended_by"), m[3].to_owned());
+        }
+
+        if let Some((kind, text, _)) = self.runaway.take() {
+            details
+                .entry(String::from("structure"))
+                .or_insert_with(|| format!("runaway-{}", kind));
+            details.insert(String::from("runaway"), text.trim().to_owned());
+        }
+    }
+
     fn process_error(&mut self, m: Captures) {
         if let Some(message) = m.get(5) {
-            let mut info = MessageInfo {
-                full: m.get(0).unwrap().as_str().to_owned(),
-                details: HashMap::new(),
-                context_lines: Vec::new(),
-            };
+            let mut info = self.new_info(m.get(0).unwrap().as_str());
 
             info.details
                 .insert(String::from("message"), message.as_str().to_owned());
+            if let Some(engine) = ENGINE_ERROR.captures(message.as_str()) {
+                info.details
+                    .insert(String::from("engine"), engine[1].to_owned());
+            }
+            if let Some(capacity) = CAPACITY_EXCEEDED.captures(message.as_str()) {
+                let size = capacity[2].parse().unwrap_or(0);
+                let exceeded = resource(&capacity[1], size, size);
+                info.details
+                    .insert(String::from("resource"), exceeded.name.clone());
+                if let Some(ref setting) = exceeded.setting {
+                    info.details
+                        .insert(String::from("setting"), setting.clone());
+                }
+                self.report.resources.exceeded = Some(exceeded);
+            }
+            self.set_structure(&mut info);
+            set_code(&mut info);
             self.report.errors += 1;
             self.report.messages.push(Message::Error(info))
         } else {
-            let info = self.process_generic(m);
+            let mut info = self.process_generic(m);
+            self.set_structure(&mut info);
+            set_code(&mut info);
             self.report.errors += 1;
             self.report.messages.push(Message::Error(info))
         }
@@ -235,42 +1084,200 @@ impl<'a, B: 'a + BufRead> LogParser<'a, B> {
             report,
             reader,
             lineno: 0,
-            collect_remaining: 0,
             context_lines,
+            continuing: false,
+            error: None,
+            snippet: None,
+            runaway: None,
+            groups: false,
+            memory: false,
+            file_list: false,
+            output: None,
+            engine: None,
+            encoding: Encoding::default(),
+            files: Vec::new(),
+            page: None,
+            since_shipout: Vec::new(),
+            emitted: 0,
+            observer: Box::new(|_| {}),
         }
     }
 
+    pub fn with_encoding(mut self, encoding: Encoding) -> Self {
+        self.encoding = encoding;
+        self
+    }
+
+    pub fn with_observer<F: FnMut(ParseEvent) + 'a>(mut self, observer: F) -> Self {
+        self.observer = Box::new(observer);
+        self
+    }
+
     pub fn parse(mut self) {
         while let Some(line) = self.next_line() {
+            let line = line.trim_end_matches(['\n', '\r']);
+            if let Some(kind) = failure_kind(line) {
+                self.report.set_failure(kind);
+            }
+
+            if self.continue_message(line)
+                || self.continue_error(line)
+                || self.continue_snippet(line)
+                || self.continue_runaway(line)
+                || self.continue_groups(line)
+                || self.continue_memory(line)
+                || self.continue_output(line)
+                || self.continue_file_list(line)
+            {
+                continue;
+            }
 
-            if let Some(last) = self.report.messages.last_mut() {
-                if let Some(cmpt) = last.get_component_name() {
-                    let pattern = format!("({}) ", cmpt);
-                    if line.starts_with(&pattern) {
-                        let message = line.trim_start_matches(&pattern).trim_start();
-                        last.extend_message(&message);
-                        self.collect_remaining = 0;
-                        continue;
+            self.flush_messages();
+            let count = self.report.messages.len();
+            self.parse_line(line);
+            if self.report.messages.len() > count {
+                self.estimate_pages(count);
+                self.continuing = true;
+                match self.report.messages.last() {
+                    Some(Message::Error(_)) => self.error = Some(ErrorBlock::Preamble(0)),
+                    Some(Message::Badbox(info))
+                        if info.details.get("direction").map(|d| d.as_str()) == Some("h") =>
+                    {
+                        self.snippet = Some((String::new(), 0));
                     }
+                    _ => {}
                 }
-
-                //if self.collect_remaining > 0 {
-                //    last.add_context(line);
-                //    self.collect_remaining -= 1;
-                //    continue;
-                //}
             }
+        }
+        self.end_snippet();
+        // Messages written after the last page was shipped out, such
+        // as the summary of undefined references, are not on any page.
+        for &index in &self.since_shipout {
+            self.report.messages[index].clear_page();
+        }
+        self.since_shipout.clear();
+        self.flush_messages();
+        self.report.summarise();
+    }
+}
+
+/// Whether `line` shows TeX source as part of the context of an
+/// error, such as the expansion `\foo #1->\bar {#1}` of a macro
+/// or the indented rest of a context line. Parentheses in it are
+/// not file markers.
+fn is_context_line(line: &str) -> bool {
+    (line.starts_with('\\') && line.contains("->")) || line.starts_with(' ')
+}
+
+/// The failure a line of the log shows the build ended with, if any.
+fn failure_kind(line: &str) -> Option<FailureKind> {
+    if line.starts_with("! TeX capacity exceeded") {
+        Some(FailureKind::CapacityExceeded)
+    } else if line.starts_with("*** (job aborted, no legal \\end found)") {
+        Some(FailureKind::JobAborted)
+    } else if line.starts_with("! Emergency stop") {
+        Some(FailureKind::EmergencyStop)
+    } else if line.starts_with("No pages of output.")
+        || line.contains("Fatal error occurred, no output PDF file produced!")
+    {
+        Some(FailureKind::NoOutput)
+    } else {
+        None
+    }
+}
+
+/// Record the code of errors that have an explanation.
+fn set_code(info: &mut MessageInfo) {
+    if let Some(explanation) = info.details.get("message").and_then(|m| explain_message(m)) {
+        info.details
+            .insert(String::from("code"), explanation.code.to_owned());
+    }
+}
+
+/// Record the `on input line N` part of a message as its line.
+fn set_input_line(info: &mut MessageInfo) {
+    if info.details.contains_key("line") {
+        return;
+    }
+    if let Some(line) = info
+        .details
+        .get("message")
+        .and_then(|message| INPUT_LINE.captures(message))
+    {
+        let line = line[1].to_owned();
+        info.details.insert(String::from("line"), line);
+    }
+}
 
-            self.parse_line(&line);
+/// Record the requested and substituted shapes of a font warning,
+/// once its continuation lines have been read.
+fn set_font_substitution(info: &mut MessageInfo) {
+    let (requested, substituted) = match info
+        .details
+        .get("message")
+        .and_then(|message| FONT_SUBSTITUTION.captures(message))
+    {
+        Some(m) => match (m.get(2), m.get(3), m.get(4)) {
+            (_, Some(used), _) => (m[1].to_owned(), used.as_str().to_owned()),
+            (Some(size), _, Some(used)) => (
+                format!("{} at {}", &m[1], size.as_str()),
+                format!("{} at {}", &m[1], used.as_str()),
+            ),
+            _ => return,
+        },
+        None => return,
+    };
+    info.details
+        .insert(String::from("requested_font"), requested);
+    info.details
+        .insert(String::from("substituted_font"), substituted);
+}
+
+fn is_file_name(name: &str) -> bool {
+    if name.starts_with("./") || name.starts_with("../") || name.starts_with('/') {
+        return true;
+    }
+    match name.rfind('.') {
+        Some(i) if i > 0 && i + 1 < name.len() => {
+            let first = name.chars().next().unwrap();
+            (first.is_alphabetic() || first == '_')
+                && name[i + 1..].chars().all(|c| c.is_ascii_alphanumeric())
         }
+        _ => false,
     }
 }
 
 pub fn parse_log<R: Read>(log: R) -> BuildReport {
+    parse_log_streaming(log, |_| {})
+}
+
+/// Parse a log written in `encoding`.
+pub fn parse_log_encoded<R: Read>(log: R, encoding: Encoding) -> BuildReport {
+    parse_log_streaming_encoded(log, encoding, |_| {})
+}
+
+/// Parse a log as it is being written, passing events to `observer`
+/// as soon as they are found in the log.
+pub fn parse_log_streaming<R, F>(log: R, observer: F) -> BuildReport
+where
+    R: Read,
+    F: FnMut(ParseEvent),
+{
+    parse_log_streaming_encoded(log, Encoding::default(), observer)
+}
+
+/// Parse a log written in `encoding` as it is being written.
+pub fn parse_log_streaming_encoded<R, F>(log: R, encoding: Encoding, observer: F) -> BuildReport
+where
+    R: Read,
+    F: FnMut(ParseEvent),
+{
     let reader = BufReader::new(log);
     let mut report = BuildReport::new();
 
-    let parser: LogParser<BufReader<R>> = LogParser::new(&mut report, reader, 2);
+    let parser = LogParser::new(&mut report, reader, 4)
+        .with_encoding(encoding)
+        .with_observer(observer);
 
     parser.parse();
 
@@ -281,15 +1288,25 @@ pub fn parse_log<R: Read>(log: R) -> BuildReport {
 mod tests {
     use super::*;
 
+    use std::io;
+
     fn create_parser(line: &str) -> BuildReport {
-        let mut cursor = io::Cursor::new(&line);
-        let mut reader = BufReader::new(cursor);
+        let cursor = io::Cursor::new(&line);
+        let reader = BufReader::new(cursor);
         let mut report = BuildReport::new();
-        let mut parser = LogParser::new(&mut report, reader, 2);
-        parser.parse_line(&line);
+        {
+            let mut parser = LogParser::new(&mut report, reader, 2);
+            parser.parse_line(&line);
+        }
         report
     }
 
+    fn collect_events(log: &str) -> Vec<ParseEvent> {
+        let mut events = Vec::new();
+        parse_log_streaming(log.as_bytes(), |e| events.push(e));
+        events
+    }
+
     #[test]
     fn test_underfull_vbox_while_output_active() {
         let line = "Underfull \\vbox (badness 1234) has occurred while \\output is active []";
@@ -474,4 +1491,533 @@ mod tests {
         assert_eq!(report.badboxes, 1);
     }
 
+    #[test]
+    fn test_page_markers_tracked() {
+        let log = "[1] [2 <./fig.png>]\n[3{/usr/share/texmf/pdftex.map}]\n[]\\OT1/cmr/m/n/10 text\n";
+        let pages: Vec<usize> = collect_events(log)
+            .into_iter()
+            .filter_map(|e| match e {
+                ParseEvent::Page(p) => Some(p),
+                _ => None,
+            })
+            .collect();
+
+        assert_eq!(pages, vec![1, 2, 3]);
+    }
+
+    #[test]
+    fn test_file_stack_tracked() {
+        let log = "(./main.tex (/usr/share/article.cls (see the transcript) )\n(./chapters/results.tex\n";
+        let mut current = None;
+        for event in collect_events(log) {
+            match event {
+                ParseEvent::FileOpened(f) => current = Some(f),
+                ParseEvent::FileClosed(f) => current = f,
+                _ => {}
+            }
+        }
+
+        assert_eq!(current, Some(String::from("chapters/results.tex")));
+    }
+
+    #[test]
+    fn test_structure_around_messages() {
+        let log = concat!(
+            "(./main.tex (./chapter.tex\n",
+            "! Undefined control sequence.\n",
+            "\\foo #1->\\bar (#1\n",
+            "                 \n",
+            "l.5 \\foo{x}\n",
+            "           \n",
+            "The control sequence at the end of the top line\n",
+            "\n",
+            "Overfull \\vbox (2.0pt too high) detected at line 7 [3]\n",
+            ")\n",
+            "LaTeX Warning: Something odd on input line 9.\n",
+            "\n",
+        );
+        let events = collect_events(log);
+        let report = parse_log(log.as_bytes());
+
+        assert!(events.iter().any(|e| matches!(e, ParseEvent::Page(3))));
+        let files: Vec<Option<&str>> = report.messages.iter().map(|m| m.file()).collect();
+        assert_eq!(files, vec![Some("chapter.tex"), Some("chapter.tex"), Some("main.tex")]);
+    }
+
+    #[test]
+    fn test_messages_emitted_after_continuation() {
+        let log = "Package hyperref Warning: Token not allowed\n(hyperref) removing `math shift'.\n";
+        let messages: Vec<Message> = collect_events(log)
+            .into_iter()
+            .filter_map(|e| match e {
+                ParseEvent::Message(m) => Some(m),
+                _ => None,
+            })
+            .collect();
+
+        assert_eq!(messages.len(), 1);
+        let message = messages[0].as_ref().unwrap().details.get("message").unwrap();
+        assert_eq!(message, "Token not allowed removing `math shift'.");
+    }
+
+    #[test]
+    fn test_engine_warnings_are_tagged() {
+        let log = "This is LuaHBTeX, Version 1.17.0 (TeX Live 2023)\n\
+LuaTeX warning: Font map file not found\n\
+Missing character: There is no \u{3b1} (U+03B1) in font [lmroman10-regular]:+tlig;!\n\
+luaotfload | aux : font no 43 (nil) does not define feature smcp\n\
+luaotfload | db : Font names database loaded in 3.2 ms\n\
+xdvipdfmx:warning: Could not locate a virtual/physical font for TFM \"foo\".\n\
+pdfTeX warning: pdflatex (file ./fig.pdf): PDF inclusion: found PDF version <1.7>, but at most version <1.5> allowed\n";
+        let report = parse_log(log.as_bytes());
+
+        assert_eq!(report.warnings, 5);
+        assert_eq!(report.info, 1);
+        let engines: Vec<&str> = report
+            .messages
+            .iter()
+            .map(|m| m.as_ref().unwrap().details["engine"].as_str())
+            .collect();
+        assert_eq!(
+            engines,
+            vec!["LuaTeX", "LuaHBTeX", "LuaTeX", "LuaTeX", "XeTeX", "pdfTeX"]
+        );
+        let missing = report.messages[1].as_ref().unwrap();
+        assert_eq!(missing.details["codepoint"], "03B1");
+        assert_eq!(missing.details["font"], "[lmroman10-regular]:+tlig;");
+    }
+
+    #[test]
+    fn test_font_report() {
+        let log = "(./main.tex\n\
+LaTeX Font Warning: Font shape `OT1/cmr/bx/sc' undefined\n\
+(Font)              using `OT1/cmr/bx/n' instead on input line 3.\n\
+\n\
+LaTeX Font Warning: Font shape `OT1/cmr/m/n' in size <5.5> not available\n\
+(Font)              size <5> substituted on input line 8.\n\
+Missing character: There is no \u{3b1} in font cmr10!\n\
+Missing character: There is no \u{3b2} in font cmr10!\n\
+Missing character: There is no \u{3b1} in font cmr10!\n\
+)\n\
+LaTeX Font Warning: Some font shapes were not available, defaults substituted.\n";
+        let report = parse_log(log.as_bytes());
+        let fonts = &report.fonts;
+
+        assert_eq!(fonts.missing_glyphs["cmr10"], vec!["\u{3b1}", "\u{3b2}"]);
+        assert_eq!(
+            fonts.substitutions,
+            vec![
+                FontSubstitution {
+                    requested: String::from("OT1/cmr/bx/sc"),
+                    substituted: String::from("OT1/cmr/bx/n"),
+                    file: Some(String::from("main.tex")),
+                    line: Some(3),
+                },
+                FontSubstitution {
+                    requested: String::from("OT1/cmr/m/n at 5.5"),
+                    substituted: String::from("OT1/cmr/m/n at 5"),
+                    file: Some(String::from("main.tex")),
+                    line: Some(8),
+                },
+            ]
+        );
+        assert!(fonts.defaults_substituted);
+    }
+
+    #[test]
+    fn test_multiply_defined_keys() {
+        let log = "LaTeX Warning: Label `fig:a' multiply defined.\n\
+pdfTeX warning (ext4): destination with the same identifier (name{page.1}) has been already used, duplicate ignored\n\
+Package biblatex Warning: Duplicate entry key 'knuth' in file 'refs.bib'.\n\
+LaTeX Warning: There were multiply-defined labels.\n";
+        let report = parse_log(log.as_bytes());
+
+        assert_eq!(report.multiply_defined, 3);
+        assert_eq!(report.warnings, 0);
+        assert_eq!(report.info, 1);
+        let kinds: Vec<DuplicateKind> = report
+            .messages
+            .iter()
+            .filter_map(|m| match m {
+                Message::MultiplyDefined { kind, .. } => Some(*kind),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(
+            kinds,
+            vec![DuplicateKind::Label, DuplicateKind::Destination, DuplicateKind::BibEntry]
+        );
+    }
+
+    #[test]
+    fn test_badbox_snippet_and_counts() {
+        let log = "(./main.tex\n\
+Overfull \\hbox (15.74872pt too wide) in paragraph at lines 12--14\n\
+[]\\T1/lmr/m/n/10 (+20) This is some text that goes on and \n\
+on[] \n\
+ []\n\
+\n\
+Underfull \\hbox (badness 1033) in paragraph at lines 20--21\n\
+[]\\T1/lmr/m/n/10 Short\n\
+\n\
+Underfull \\vbox (badness 10000) has occurred while \\output is active [3]\n\
+)\n";
+        let mut report = parse_log(log.as_bytes());
+
+        let overfull = report.messages[0].as_ref().unwrap();
+        assert_eq!(
+            overfull.details["snippet"],
+            "This is some text that goes on and on"
+        );
+        assert_eq!(overfull.details["file"], "main.tex");
+        assert_eq!(report.messages[0].line(), Some(12));
+        assert_eq!(
+            report.badbox_counts,
+            BadboxCounts {
+                overfull_hbox: 1,
+                overfull_vbox: 0,
+                underfull_hbox: 1,
+                underfull_vbox: 1,
+            }
+        );
+
+        report.filter_badboxes(&BadboxThresholds {
+            min_overfull: 10.0,
+            min_badness: 5000,
+        });
+        assert_eq!(report.badboxes, 2);
+        assert_eq!(report.badbox_counts.underfull_hbox, 0);
+    }
+
+    #[test]
+    fn test_invalid_bytes_do_not_end_parse() {
+        let log: &[u8] = b"(./caf\xe9.tex\n! Package inputenc Error: Invalid UTF-8 byte \"E9.\n\nl.3 caf\xe9\n\n! Undefined control sequence.\n)\n";
+
+        let report = parse_log(log);
+        assert_eq!(report.errors, 2);
+        assert_eq!(report.undecodable_lines, 2);
+
+        let report = parse_log_encoded(log, Encoding::Latin1);
+        assert_eq!(report.errors, 2);
+        assert_eq!(report.undecodable_lines, 0);
+        assert_eq!(report.messages[0].file(), Some("caf\u{e9}.tex"));
+    }
+
+    #[test]
+    fn test_runaway_argument() {
+        let log = "(./main.tex\n\
+Runaway argument?\n\
+{This is bold text (never closed \\par \n\
+! Paragraph ended before \\textbf was complete.\n\
+<to be read again> \n\
+                   \\par \n\
+l.12 \n\
+     \n\
+I suspect you've forgotten a `}'.\n\
+\n\
+)\n";
+        let report = parse_log(log.as_bytes());
+
+        let details = &report.messages[0].as_ref().unwrap().details;
+        assert_eq!(details["structure"], "paragraph-ended");
+        assert_eq!(details["command"], "\\textbf");
+        assert_eq!(details["runaway"], "{This is bold text (never closed \\par");
+        assert_eq!(report.messages[0].line(), Some(12));
+    }
+
+    #[test]
+    fn test_environment_mismatch() {
+        let report = create_parser("! LaTeX Error: \\begin{itemize} on input line 5 ended by \\end{document}.");
+
+        let details = &report.messages[0].as_ref().unwrap().details;
+        assert_eq!(details["structure"], "environment-mismatch");
+        assert_eq!(details["environment"], "itemize");
+        assert_eq!(details["started_line"], "5");
+        assert_eq!(details["ended_by"], "document");
+    }
+
+    #[test]
+    fn test_unclosed_groups() {
+        let log = "(\\end occurred inside a group at level 2)\n\
+\n\
+### simple group (level 2) entered at line 50 ({)\n\
+### semi simple group (level 1) entered at line 42 (\\begingroup)\n\
+### bottom level\n\
+(\\end occurred when \\ifx on line 10 was incomplete)\n";
+        let report = parse_log(log.as_bytes());
+
+        assert_eq!(report.warnings, 2);
+        let group = report.messages[0].as_ref().unwrap();
+        assert_eq!(group.details["structure"], "unclosed-group");
+        assert_eq!(group.details["started_line"], "50");
+        assert_eq!(
+            group.details["groups"],
+            "simple group (level 2) at line 50; semi simple group (level 1) at line 42"
+        );
+        let conditional = report.messages[1].as_ref().unwrap();
+        assert_eq!(conditional.details["command"], "\\ifx");
+        assert_eq!(report.messages[1].line(), Some(10));
+    }
+
+    #[test]
+    fn test_failure_kinds() {
+        let recoverable = parse_log("! Undefined control sequence.\nl.3 \\foo\n\nOutput written on main.pdf (1 page, 1234 bytes).\n".as_bytes());
+        assert_eq!(recoverable.failure, Some(FailureKind::Recoverable));
+
+        let log = "! Emergency stop.\n<*> main.tex\n\n*** (job aborted, no legal \\end found)\n\n!  ==> Fatal error occurred, no output PDF file produced!\n";
+        assert_eq!(parse_log(log.as_bytes()).failure, Some(FailureKind::JobAborted));
+
+        let empty = parse_log("No pages of output.\nTranscript written on main.log.\n".as_bytes());
+        assert_eq!(empty.errors, 0);
+        assert_eq!(empty.failure, Some(FailureKind::NoOutput));
+
+        let mut report = parse_log("! TeX capacity exceeded, sorry [main memory size=5000000].\n".as_bytes());
+        report.retain_messages(|_| false);
+        assert_eq!(report.failure, Some(FailureKind::CapacityExceeded));
+
+        assert_eq!(parse_log("LaTeX Warning: Empty document.\n".as_bytes()).failure, None);
+    }
+
+    #[test]
+    fn test_memory_usage() {
+        // Lines of the memory summary start with a space.
+        let log = concat!(
+            "! TeX capacity exceeded, sorry [main memory size=5000000].\n",
+            "\\foo ->\\foo \\foo \n",
+            "l.4 \\foo\n",
+            "\n",
+            "Here is how much of TeX's memory you used:\n",
+            " 5 strings out of 478287\n",
+            " 100 string characters out of 5849356\n",
+            " 5000000 words of memory out of 5000000\n",
+            " 18528 multiletter control sequences out of 15000+600000\n",
+            " 403430 words of font info for 27 fonts, out of 8000000 for 9000\n",
+            " 1141 hyphenation exceptions out of 8191\n",
+            " 55i,5n,62p,9000b,182s stack positions out of 10000i,1000n,20000p,10000b,200000s\n",
+            "No pages of output.\n",
+        );
+        let report = parse_log(log.as_bytes());
+
+        let resources = &report.resources;
+        assert_eq!(resources.limits.len(), 12);
+        let exceeded = resources.exceeded.as_ref().unwrap();
+        assert_eq!(exceeded.name, "main memory size");
+        assert_eq!(exceeded.setting.as_deref(), Some("main_memory"));
+        assert_eq!(resources.limits[3].limit, 615000);
+        let fullest = resources.fullest();
+        assert_eq!(fullest[0].name, "buffer size");
+        assert_eq!(fullest[0].used, 9000);
+        assert_eq!(report.messages[0].as_ref().unwrap().details["setting"], "main_memory");
+        assert_eq!(report.failure, Some(FailureKind::CapacityExceeded));
+    }
+
+    #[test]
+    fn test_output_and_banner() {
+        let log = concat!(
+            "This is pdfTeX, Version 3.14159265-2.6-1.40.20 (TeX Live 2019) (preloaded format=pdflatex 2019.5.8)  8 MAY 2019 12:00\n",
+            "(./main.tex\n",
+            ")\n",
+            "Output written on /home/user/documents/a-rather-long-directory-name/thesi\n",
+            "s.pdf (12 pages, 345678 bytes).\n",
+            "Transcript written on thesis.log.\n",
+        );
+        let report = parse_log(log.as_bytes());
+
+        let output = &report.output;
+        assert_eq!(
+            output.file.as_deref(),
+            Some("/home/user/documents/a-rather-long-directory-name/thesis.pdf")
+        );
+        assert_eq!(output.pages, Some(12));
+        assert_eq!(output.bytes, Some(345678));
+        assert_eq!(output.engine.as_deref(), Some("pdfTeX"));
+        assert_eq!(output.engine_version.as_deref(), Some("3.14159265-2.6-1.40.20"));
+        assert_eq!(output.distribution.as_deref(), Some("TeX Live 2019"));
+        assert_eq!(output.year, Some(2019));
+
+        let miktex = parse_log("This is XeTeX, Version 3.14159265-2.6-0.999991 (MiKTeX 2.9.7500 64-bit)\nOutput written on main.xdv (1 page).\n".as_bytes());
+        assert_eq!(miktex.output.year, None);
+        assert_eq!(miktex.output.pages, Some(1));
+        assert_eq!(miktex.output.bytes, None);
+    }
+
+    #[test]
+    fn test_file_list() {
+        let log = concat!(
+            " *File List*\n",
+            " article.cls    2019/10/25 v1.4k Standard LaTeX document class\n",
+            "  size10.clo    2019/10/25 v1.4k Standard LaTeX file (size option)\n",
+            "l3backend-pdfmode.def    2020-02-03 L3 backend support: PDF mode\n",
+            "  figure.png\n",
+            " ***********\n",
+            "\n",
+            "LaTeX Warning: There were undefined references.\n",
+        );
+        let report = parse_log(log.as_bytes());
+
+        assert_eq!(report.packages.len(), 4);
+        assert_eq!(
+            report.packages[0],
+            PackageInfo {
+                file: String::from("article.cls"),
+                date: Some(String::from("2019/10/25")),
+                version: Some(String::from("v1.4k")),
+                description: Some(String::from("Standard LaTeX document class")),
+            }
+        );
+        assert_eq!(report.packages[2].release().as_deref(), Some("2020-02-03"));
+        assert_eq!(report.packages[3].release(), None);
+        assert_eq!(report.warnings, 1);
+    }
+
+    #[test]
+    fn test_error_codes() {
+        let log = "! Missing $ inserted.\n<inserted text> \n                $\nl.5 a_\n       b\n\n! LaTeX Error: Environment algn undefined.\n\n! Package foo Error: Something odd.\n";
+        let report = parse_log(log.as_bytes());
+
+        let codes: Vec<Option<&str>> = report
+            .messages
+            .iter()
+            .map(|m| m.as_ref().unwrap().details.get("code").map(|c| c.as_str()))
+            .collect();
+        assert_eq!(codes, vec![Some("E002"), Some("E010"), None]);
+
+        let log = concat!(
+            "! LaTeX Error: Unicode character \u{2248} (U+2248)\n",
+            "               not set up for use with LaTeX.\n",
+            "\n",
+            "See the LaTeX manual or LaTeX Companion for explanation.\n",
+            "Type  H <return>  for immediate help.\n",
+            " ...                                              \n",
+            "                                                  \n",
+            "l.5 a \u{2248}\n",
+            "           b\n",
+        );
+        let report = parse_log(log.as_bytes());
+        let info = report.messages[0].as_ref().unwrap();
+        assert_eq!(
+            info.details["message"],
+            "Unicode character \u{2248} (U+2248) not set up for use with LaTeX."
+        );
+        assert_eq!(info.details["code"], "E026");
+        assert_eq!(info.details["line"], "5");
+    }
+
+    #[test]
+    fn test_pages_of_messages() {
+        let log = concat!(
+            "(./main.tex\n",
+            "Overfull \\hbox (3.0pt too wide) in paragraph at lines 5--6\n",
+            "\n",
+            "[1] [2 <./fig.png>]\n",
+            "LaTeX Warning: Reference `fig:a' on page 2 undefined on input line 12.\n",
+            "\n",
+            "Underfull \\vbox (badness 10000) has occurred while \\output is active [7]\n",
+            "Package hyperref Warning: Token not allowed in a PDF string on input line 30.\n",
+            "\n",
+            "[3]\n",
+            "LaTeX Warning: There were undefined references.\n",
+            ")\n",
+        );
+        let report = parse_log(log.as_bytes());
+
+        let pages: Vec<Option<usize>> = report.messages.iter().map(|m| m.page()).collect();
+        assert_eq!(pages, vec![Some(1), Some(2), Some(7), Some(3), None]);
+
+        // Messages are streamed before it is known whether the page
+        // being built is ever shipped out.
+        let streamed: Vec<Option<usize>> = collect_events(log)
+            .into_iter()
+            .filter_map(|e| match e {
+                ParseEvent::Message(m) => Some(m.page()),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(streamed, vec![Some(1), Some(2), Some(7), Some(3), Some(4)]);
+    }
+
+    #[test]
+    fn test_luatex_lua_error() {
+        let report = create_parser("! LuaTeX error [\\directlua]:1: attempt to call a nil value.");
+
+        assert_eq!(report.errors, 1);
+        assert_eq!(report.messages[0].as_ref().unwrap().details["engine"], "LuaTeX");
+    }
+
+    #[test]
+    fn test_expl3_error_with_help() {
+        let log = "! Package fontspec Error: The font \"X\" cannot be found.\n\
+(fontspec)                \n\
+(fontspec)                A font might not be found for many reasons.\n\
+\n\
+For immediate help type H <return>.\n \
+...                                              \n\
+                                                  \n\
+l.5 \\setmainfont{X}\n\
+                   \n\
+A font might not be found for many reasons.\n\
+Check the spelling.\n\
+\n\
+LaTeX Warning: Label(s) may have changed.\n";
+        let report = parse_log(log.as_bytes());
+
+        assert_eq!(report.errors, 1);
+        assert_eq!(report.warnings, 1);
+        let error = &report.messages[0];
+        let info = error.as_ref().unwrap();
+        assert_eq!(
+            info.details["message"],
+            "The font \"X\" cannot be found. A font might not be found for many reasons."
+        );
+        assert_eq!(error.line(), Some(5));
+        assert_eq!(info.context_lines, vec!["l.5 \\setmainfont{X}"]);
+        assert_eq!(
+            error.help(),
+            Some("A font might not be found for many reasons. Check the spelling.")
+        );
+    }
+
+    #[test]
+    fn test_tex_error_context_without_preamble() {
+        let log = "! Undefined control sequence.\n\
+<argument> \\foo\n\
+                \n\
+l.14 \\section{\\foo}\n\
+                    bar\n\
+The control sequence at the end of the top line\n\
+of your error message was never \\def'ed.\n\
+\n\
+[1]\n";
+        let events = collect_events(log);
+        let report = parse_log(log.as_bytes());
+
+        assert_eq!(report.errors, 1);
+        let error = &report.messages[0];
+        assert_eq!(error.line(), Some(14));
+        assert_eq!(
+            error.as_ref().unwrap().context_lines,
+            vec!["<argument> \\foo", "l.14 \\section{\\foo}", "bar"]
+        );
+        assert!(error.help().unwrap().ends_with("was never \\def'ed."));
+        // The error is streamed with the page being built, before
+        // that page is shipped out.
+        match &events[events.len() - 2] {
+            ParseEvent::Message(m) => assert_eq!(m.page(), Some(1)),
+            event => panic!("unexpected event {:?}", event),
+        }
+        assert!(matches!(events.last(), Some(ParseEvent::Page(1))));
+    }
+
+    #[test]
+    fn test_latex_warning_continuation() {
+        let log = "LaTeX Warning: Some text with a\n(LaTeX)        continuation on input line 12.\n\n";
+        let report = parse_log(log.as_bytes());
+
+        assert_eq!(report.warnings, 1);
+        assert_eq!(report.messages[0].line(), Some(12));
+        assert_eq!(
+            report.messages[0].to_str(),
+            "LaTeX Warning: Some text with a continuation on input line 12."
+        );
+    }
 }
@@ -1,6 +1,7 @@
 use serde::Serialize;
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap};
 use std::fmt;
+use std::str::FromStr;
 
 #[derive(Debug, Serialize, Clone)]
 pub struct MessageInfo {
@@ -26,8 +27,31 @@ impl MessageInfo {
         self.context_lines.push(line);
     }
 
+    /// Prefix of the lines that continue this message, such as
+    /// `(fontspec)` or `(LaTeX)`.
+    fn continuation_prefix(&self) -> Option<String> {
+        match self.get_component_name() {
+            Some(name) => Some(format!("({})", name)),
+            None if self.details.get("type").map(|t| t.as_str()) == Some("LaTeX") => {
+                Some(String::from("(LaTeX)"))
+            }
+            None => None,
+        }
+    }
+
+    fn add_help(&mut self, line: &str) {
+        let help = self.details.entry(String::from("help")).or_default();
+        if !help.is_empty() {
+            help.push(' ');
+        }
+        help.push_str(line);
+    }
+
     fn extend_message(&mut self, message: &str) {
+        self.full.push(' ');
+        self.full.push_str(message);
         if let Some(current) = self.details.get_mut("message") {
+            current.push(' ');
             current.push_str(message);
         } else {
             self.details
@@ -36,20 +60,115 @@ impl MessageInfo {
     }
 }
 
+/// How serious a message is, from least to most.
+#[derive(Debug, Serialize, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Severity {
+    Info,
+    Warning,
+    Error,
+}
+
+impl fmt::Display for Severity {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match self {
+            Severity::Info => "info",
+            Severity::Warning => "warning",
+            Severity::Error => "error",
+        };
+        f.pad(name)
+    }
+}
+
+impl FromStr for Severity {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Severity, String> {
+        match s {
+            "info" => Ok(Severity::Info),
+            "warning" => Ok(Severity::Warning),
+            "error" => Ok(Severity::Error),
+            _ => Err(format!(
+                "Unrecognised severity: {} (expected info, warning or error)",
+                s
+            )),
+        }
+    }
+}
+
+/// What a key that is defined more than once names.
+#[derive(Debug, Serialize, Clone, Copy, PartialEq, Eq)]
+pub enum DuplicateKind {
+    /// A label for cross references.
+    Label,
+    /// A PDF destination, as written by hyperref.
+    Destination,
+    /// A bibliography entry.
+    BibEntry,
+}
+
+impl fmt::Display for DuplicateKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            DuplicateKind::Label => write!(f, "label"),
+            DuplicateKind::Destination => write!(f, "destination"),
+            DuplicateKind::BibEntry => write!(f, "bibliography entry"),
+        }
+    }
+}
+
+/// Why a build failed, from least to most serious.
+#[derive(Debug, Serialize, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum FailureKind {
+    /// Errors were reported, but the engine carried on and could
+    /// still write its output.
+    Recoverable,
+    /// The engine finished without writing any pages.
+    NoOutput,
+    /// The engine gave up at an error, usually because it could not
+    /// ask for input in batch or nonstop mode.
+    EmergencyStop,
+    /// The input ended without `\end` or `\end{document}`.
+    JobAborted,
+    /// A table of the engine overflowed.
+    CapacityExceeded,
+}
+
+impl FailureKind {
+    /// Whether the build stopped without producing usable output.
+    pub fn is_fatal(self) -> bool {
+        self != FailureKind::Recoverable
+    }
+}
+
+impl fmt::Display for FailureKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            FailureKind::Recoverable => write!(f, "recoverable errors"),
+            FailureKind::NoOutput => write!(f, "no output"),
+            FailureKind::EmergencyStop => write!(f, "emergency stop"),
+            FailureKind::JobAborted => write!(f, "job aborted, no legal \\end found"),
+            FailureKind::CapacityExceeded => write!(f, "TeX capacity exceeded"),
+        }
+    }
+}
+
 #[derive(Debug, Serialize, Clone)]
 pub enum Message {
     Error(MessageInfo),
     Warning(MessageInfo),
     Badbox(MessageInfo),
     Info(MessageInfo),
-    MissingCitation { label: String },
-    MissingReference { label: String },
+    MissingCitation { label: String, page: Option<usize> },
+    MissingReference { label: String, page: Option<usize> },
+    MultiplyDefined { label: String, kind: DuplicateKind, page: Option<usize> },
 }
 
 use Message::*;
 
 impl Message {
-    pub(crate) fn get_component_name(&self) -> Option<&String> {
+    /// Name of the package, class or TeX component that wrote the
+    /// message, if any.
+    pub fn get_component_name(&self) -> Option<&String> {
         match self {
             Error(ref inner) | Warning(ref inner) | Info(ref inner) => {
                 inner.get_component_name()
@@ -68,6 +187,23 @@ impl Message {
         }
     }
 
+    pub(crate) fn add_help(&mut self, line: &str) {
+        if let Some(inner) = self.as_mut() {
+            inner.add_help(line);
+        }
+    }
+
+    pub(crate) fn continuation_prefix(&self) -> Option<String> {
+        self.as_ref().and_then(|inner| inner.continuation_prefix())
+    }
+
+    /// Help text that TeX wrote after an error, if any.
+    pub fn help(&self) -> Option<&str> {
+        self.as_ref()
+            .and_then(|inner| inner.details.get("help"))
+            .map(|h| h.as_str())
+    }
+
     pub fn as_ref(&self) -> Option<&MessageInfo> {
         match self {
             Error(ref inner) => Some(inner),
@@ -88,6 +224,90 @@ impl Message {
         }
     }
 
+    /// Name of the kind of message, as used to select messages by
+    /// category.
+    pub fn category(&self) -> &'static str {
+        match self {
+            Error(_) => "error",
+            Warning(_) => "warning",
+            Badbox(_) => "badbox",
+            Info(_) => "info",
+            MissingCitation { .. } => "missing-citation",
+            MissingReference { .. } => "missing-reference",
+            MultiplyDefined { .. } => "multiply-defined",
+        }
+    }
+
+    pub fn severity(&self) -> Severity {
+        match self {
+            Error(_) => Severity::Error,
+            Info(_) => Severity::Info,
+            _ => Severity::Warning,
+        }
+    }
+
+    /// File being read when the message was written, if known.
+    pub fn file(&self) -> Option<&str> {
+        self.as_ref()
+            .and_then(|inner| inner.details.get("file"))
+            .map(|f| f.as_str())
+    }
+
+    /// Line of the source that the message refers to, if known.
+    pub fn line(&self) -> Option<usize> {
+        let details = &self.as_ref()?.details;
+        details
+            .get("line")
+            .or_else(|| details.get("start_line"))
+            .and_then(|l| l.parse().ok())
+    }
+
+    /// Page of the output the message is about, if known. Unless TeX
+    /// names the page, this is the page that was being built when the
+    /// message was written.
+    pub fn page(&self) -> Option<usize> {
+        match self {
+            MissingCitation { page, .. }
+            | MissingReference { page, .. }
+            | MultiplyDefined { page, .. } => *page,
+            _ => self.as_ref()?.details.get("page")?.parse().ok(),
+        }
+    }
+
+    /// Set the page of the message, unless it is already known.
+    /// Returns whether it was set.
+    pub(crate) fn estimate_page(&mut self, estimate: usize) -> bool {
+        match self {
+            MissingCitation { page, .. }
+            | MissingReference { page, .. }
+            | MultiplyDefined { page, .. } => {
+                if page.is_some() {
+                    return false;
+                }
+                *page = Some(estimate);
+            }
+            _ => {
+                let details = &mut self.as_mut().unwrap().details;
+                if details.contains_key("page") {
+                    return false;
+                }
+                details.insert(String::from("page"), estimate.to_string());
+            }
+        }
+        true
+    }
+
+    pub(crate) fn clear_page(&mut self) {
+        match self {
+            MissingCitation { page, .. }
+            | MissingReference { page, .. }
+            | MultiplyDefined { page, .. } => *page = None,
+            _ => {
+                self.as_mut().unwrap().details.remove("page");
+            }
+        }
+    }
+
     pub fn to_str(&self) -> String {
         use Message::*;
         match self {
@@ -95,8 +315,283 @@ impl Message {
             Warning(ref inner) => inner.full.clone(),
             Info(ref inner) => inner.full.clone(),
             Badbox(ref inner) => inner.full.clone(),
-            MissingCitation { label } => format!("Missing citation: {}", &label),
-            MissingReference { label } => format!("Missing reference: {}", &label),
+            MissingCitation { label, .. } => format!("Missing citation: {}", &label),
+            MissingReference { label, .. } => format!("Missing reference: {}", &label),
+            MultiplyDefined { label, kind, .. } => format!("Multiply defined {}: {}", kind, &label),
+        }
+    }
+}
+
+/// A font shape that LaTeX replaced with another.
+#[derive(Debug, Serialize, Clone, PartialEq)]
+pub struct FontSubstitution {
+    pub requested: String,
+    pub substituted: String,
+    pub file: Option<String>,
+    pub line: Option<usize>,
+}
+
+/// Problems with fonts, collected from the messages of a log.
+#[derive(Debug, Serialize, Clone, Default)]
+pub struct FontReport {
+    /// Characters missing from each font, in the order they were
+    /// first found. Only written when `\tracinglostchars` is set.
+    pub missing_glyphs: BTreeMap<String, Vec<String>>,
+    pub substitutions: Vec<FontSubstitution>,
+    /// Whether LaTeX reported that some font shapes were not
+    /// available and defaults were used.
+    pub defaults_substituted: bool,
+}
+
+impl FontReport {
+    fn from_messages(messages: &[Message]) -> FontReport {
+        let mut fonts = FontReport::default();
+        for message in messages {
+            let details = match message.as_ref() {
+                Some(inner) => &inner.details,
+                None => continue,
+            };
+            if let (Some(font), Some(character)) = (details.get("font"), details.get("character")) {
+                let character = match details.get("codepoint") {
+                    Some(code) => format!("{} (U+{})", character, code),
+                    None => character.clone(),
+                };
+                let missing = fonts.missing_glyphs.entry(font.clone()).or_default();
+                if !missing.contains(&character) {
+                    missing.push(character);
+                }
+            }
+            if let (Some(requested), Some(substituted)) =
+                (details.get("requested_font"), details.get("substituted_font"))
+            {
+                fonts.substitutions.push(FontSubstitution {
+                    requested: requested.clone(),
+                    substituted: substituted.clone(),
+                    file: message.file().map(|f| f.to_owned()),
+                    line: message.line(),
+                });
+            }
+            if details
+                .get("message")
+                .is_some_and(|m| m.starts_with("Some font shapes were not available"))
+            {
+                fonts.defaults_substituted = true;
+            }
+        }
+        fonts
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.missing_glyphs.is_empty() && self.substitutions.is_empty() && !self.defaults_substituted
+    }
+}
+
+/// Use of one of the fixed size tables of the engine.
+#[derive(Debug, Serialize, Clone, PartialEq)]
+pub struct ResourceLimit {
+    /// Name of the table, as in `TeX capacity exceeded` errors.
+    pub name: String,
+    /// Variable of `texmf.cnf` that sets the size of the table, if
+    /// it can be changed.
+    pub setting: Option<String>,
+    pub used: u64,
+    pub limit: u64,
+}
+
+impl ResourceLimit {
+    /// How full the table is, from 0 to 1.
+    pub fn fraction(&self) -> f64 {
+        if self.limit == 0 {
+            0.0
+        } else {
+            self.used as f64 / self.limit as f64
+        }
+    }
+}
+
+impl fmt::Display for ResourceLimit {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{} {} of {} ({:.0}%)",
+            self.name,
+            self.used,
+            self.limit,
+            self.fraction() * 100.0
+        )?;
+        if let Some(ref setting) = self.setting {
+            write!(f, ", set by {}", setting)?;
+        }
+        Ok(())
+    }
+}
+
+/// How much of its memory the engine used, from the summary at the
+/// end of the log.
+#[derive(Debug, Serialize, Clone, Default)]
+pub struct ResourceUsage {
+    pub limits: Vec<ResourceLimit>,
+    /// The table that overflowed, if the build stopped with `TeX
+    /// capacity exceeded`.
+    pub exceeded: Option<ResourceLimit>,
+}
+
+impl ResourceUsage {
+    /// The tables used the most, fullest first, leaving out the one
+    /// that overflowed.
+    pub fn fullest(&self) -> Vec<&ResourceLimit> {
+        let exceeded = self.exceeded.as_ref().map(|e| e.name.as_str());
+        let mut limits: Vec<&ResourceLimit> = self
+            .limits
+            .iter()
+            .filter(|l| Some(l.name.as_str()) != exceeded)
+            .collect();
+        limits.sort_by(|a, b| b.fraction().total_cmp(&a.fraction()));
+        limits
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.limits.is_empty() && self.exceeded.is_none()
+    }
+}
+
+/// The file written by the build, and the engine that wrote it.
+#[derive(Debug, Serialize, Clone, Default, PartialEq)]
+pub struct BuildOutput {
+    /// File named in the `Output written on` line of the log.
+    pub file: Option<String>,
+    pub pages: Option<usize>,
+    pub bytes: Option<u64>,
+    pub engine: Option<String>,
+    pub engine_version: Option<String>,
+    /// Distribution named in the banner, such as `TeX Live 2019`.
+    pub distribution: Option<String>,
+    pub year: Option<u16>,
+    /// Path of the output file, once it has been found on disk.
+    pub path: Option<String>,
+    /// SHA-256 hash of the output file, once it has been found on
+    /// disk.
+    pub sha256: Option<String>,
+    /// Why the output file could not be found or read.
+    pub error: Option<String>,
+}
+
+impl fmt::Display for BuildOutput {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.file {
+            Some(ref file) => write!(f, "{}", file)?,
+            None => write!(f, "no output")?,
+        }
+        if let Some(pages) = self.pages {
+            write!(f, ", {} page{}", pages, if pages == 1 { "" } else { "s" })?;
+        }
+        if let Some(bytes) = self.bytes {
+            write!(f, ", {} bytes", bytes)?;
+        }
+        if let Some(ref engine) = self.engine {
+            write!(f, " ({}", engine)?;
+            if let Some(ref version) = self.engine_version {
+                write!(f, " {}", version)?;
+            }
+            if let Some(ref distribution) = self.distribution {
+                write!(f, ", {}", distribution)?;
+            }
+            write!(f, ")")?;
+        }
+        Ok(())
+    }
+}
+
+/// A file listed by `\\listfiles`, with the version it declares.
+#[derive(Debug, Serialize, Clone, PartialEq)]
+pub struct PackageInfo {
+    pub file: String,
+    pub date: Option<String>,
+    pub version: Option<String>,
+    pub description: Option<String>,
+}
+
+impl PackageInfo {
+    /// Date and version, as recorded in lockfiles.
+    pub fn release(&self) -> Option<String> {
+        match (&self.date, &self.version) {
+            (Some(date), Some(version)) => Some(format!("{} {}", date, version)),
+            (Some(date), None) => Some(date.clone()),
+            (None, Some(version)) => Some(version.clone()),
+            (None, None) => None,
+        }
+    }
+}
+
+/// Number of bad boxes of each kind.
+#[derive(Debug, Serialize, Clone, Copy, Default, PartialEq, Eq)]
+pub struct BadboxCounts {
+    pub overfull_hbox: usize,
+    pub overfull_vbox: usize,
+    pub underfull_hbox: usize,
+    pub underfull_vbox: usize,
+}
+
+impl BadboxCounts {
+    fn from_messages(messages: &[Message]) -> BadboxCounts {
+        let mut counts = BadboxCounts::default();
+        for message in messages {
+            let details = match message {
+                Badbox(ref inner) => &inner.details,
+                _ => continue,
+            };
+            let kind = (
+                details.get("type").map(|t| t.as_str()),
+                details.get("direction").map(|d| d.as_str()),
+            );
+            match kind {
+                (Some("Over"), Some("h")) => counts.overfull_hbox += 1,
+                (Some("Over"), Some("v")) => counts.overfull_vbox += 1,
+                (Some("Under"), Some("h")) => counts.underfull_hbox += 1,
+                (Some("Under"), Some("v")) => counts.underfull_vbox += 1,
+                _ => {}
+            }
+        }
+        counts
+    }
+}
+
+impl fmt::Display for BadboxCounts {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "overfull \\hbox: {}, overfull \\vbox: {}, underfull \\hbox: {}, underfull \\vbox: {}",
+            self.overfull_hbox, self.overfull_vbox, self.underfull_hbox, self.underfull_vbox
+        )
+    }
+}
+
+/// The smallest bad boxes that are worth reporting.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct BadboxThresholds {
+    /// Points by which a box must be overfull.
+    pub min_overfull: f64,
+    /// Badness of an underfull box, from 0 to 10000.
+    pub min_badness: u32,
+}
+
+impl BadboxThresholds {
+    /// Whether `message` is kept under these thresholds. Messages
+    /// other than bad boxes are always kept.
+    pub fn keeps(&self, message: &Message) -> bool {
+        let details = match message {
+            Badbox(ref inner) => &inner.details,
+            _ => return true,
+        };
+        let by = details.get("by").map(|b| b.trim_end_matches("pt"));
+        match details.get("type").map(|t| t.as_str()) {
+            Some("Over") => by
+                .and_then(|b| b.parse::<f64>().ok())
+                .is_none_or(|points| points >= self.min_overfull),
+            Some("Under") => by
+                .and_then(|b| b.parse::<u32>().ok())
+                .is_none_or(|badness| badness >= self.min_badness),
+            _ => true,
         }
     }
 }
@@ -106,23 +601,107 @@ pub struct BuildReport {
     pub errors: usize,
     pub warnings: usize,
     pub badboxes: usize,
+    pub badbox_counts: BadboxCounts,
     pub info: usize,
     pub missing_references: usize,
     pub missing_citations: usize,
+    pub multiply_defined: usize,
     pub messages: Vec<Message>,
+    /// Messages hidden by suppression rules. They are not counted.
+    pub suppressed: Vec<Message>,
+    pub fonts: FontReport,
+    /// Lines of the log with bytes that could not be decoded, and
+    /// were replaced with U+FFFD.
+    pub undecodable_lines: usize,
+    /// Why the build failed, if it did.
+    pub failure: Option<FailureKind>,
+    pub resources: ResourceUsage,
+    pub output: BuildOutput,
+    /// Files listed by `\\listfiles` at the end of the log.
+    pub packages: Vec<PackageInfo>,
 }
 
 impl BuildReport {
     pub(crate) fn new() -> BuildReport {
         BuildReport {
             messages: Vec::new(),
+            suppressed: Vec::new(),
             errors: 0,
             warnings: 0,
             badboxes: 0,
+            badbox_counts: BadboxCounts::default(),
             info: 0,
             missing_citations: 0,
             missing_references: 0,
+            multiply_defined: 0,
+            fonts: FontReport::default(),
+            undecodable_lines: 0,
+            failure: None,
+            resources: ResourceUsage::default(),
+            output: BuildOutput::default(),
+            packages: Vec::new(),
+        }
+    }
+
+    /// Keep only the messages for which `keep` returns true, and
+    /// update the counts to match.
+    pub fn retain_messages<F: FnMut(&Message) -> bool>(&mut self, keep: F) {
+        self.messages.retain(keep);
+        self.recount();
+    }
+
+    /// Move the messages for which `suppress` returns true to the
+    /// suppressed messages, and update the counts to match.
+    pub fn suppress<F: FnMut(&Message) -> bool>(&mut self, mut suppress: F) {
+        let (suppressed, kept): (Vec<Message>, Vec<Message>) =
+            self.messages.drain(..).partition(|m| suppress(m));
+        self.messages = kept;
+        self.suppressed.extend(suppressed);
+        self.recount();
+    }
+
+    fn recount(&mut self) {
+        self.errors = 0;
+        self.warnings = 0;
+        self.badboxes = 0;
+        self.info = 0;
+        self.missing_citations = 0;
+        self.missing_references = 0;
+        self.multiply_defined = 0;
+        for message in &self.messages {
+            match message {
+                Error(_) => self.errors += 1,
+                Warning(_) => self.warnings += 1,
+                Badbox(_) => self.badboxes += 1,
+                Info(_) => self.info += 1,
+                MissingCitation { .. } => self.missing_citations += 1,
+                MissingReference { .. } => self.missing_references += 1,
+                MultiplyDefined { .. } => self.multiply_defined += 1,
+            }
         }
+        self.summarise();
+    }
+
+    /// Drop the bad boxes that are too small to be worth reporting.
+    pub fn filter_badboxes(&mut self, thresholds: &BadboxThresholds) {
+        self.retain_messages(|m| thresholds.keeps(m));
+    }
+
+    /// Fill in the parts of the report that are collected from the
+    /// messages as a whole.
+    pub(crate) fn summarise(&mut self) {
+        self.badbox_counts = BadboxCounts::from_messages(&self.messages);
+        self.fonts = FontReport::from_messages(&self.messages);
+        match self.failure {
+            Some(kind) if kind.is_fatal() => {}
+            _ if self.errors > 0 => self.failure = Some(FailureKind::Recoverable),
+            _ => self.failure = None,
+        }
+    }
+
+    /// Record a failure found in the log, keeping the most serious.
+    pub(crate) fn set_failure(&mut self, kind: FailureKind) {
+        self.failure = self.failure.max(Some(kind));
     }
 }
 
@@ -135,7 +714,40 @@ impl fmt::Display for BuildReport {
             self.errors,
             self.warnings,
             self.badboxes,
-        )
+        )?;
+        match self.failure {
+            Some(kind) if kind.is_fatal() => write!(f, " (fatal: {})", kind),
+            _ => Ok(()),
+        }
+    }
+
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::parser::parse_log;
+
+    #[test]
+    fn test_retain_messages_updates_counts() {
+        let log = "Package hyperref Warning: Draft mode on.\nClass article Warning: Unknown option `foo'.\n! Undefined control sequence.\n";
+        let mut report = parse_log(log.as_bytes());
+
+        report.retain_messages(|m| m.get_component_name().is_none_or(|c| c != "hyperref"));
+
+        assert_eq!(report.messages.len(), 2);
+        assert_eq!(report.warnings, 1);
+        assert_eq!(report.errors, 1);
     }
 
-}
\ No newline at end of file
+    #[test]
+    fn test_suppressed_messages_are_kept_aside() {
+        let log = "Package hyperref Warning: Draft mode on.\n! Undefined control sequence.\n";
+        let mut report = parse_log(log.as_bytes());
+
+        report.suppress(|m| m.category() == "warning");
+
+        assert_eq!(report.warnings, 0);
+        assert_eq!(report.errors, 1);
+        assert_eq!(report.suppressed.len(), 1);
+    }
+}
@@ -1,11 +1,19 @@
 use std::collections::HashMap;
 use std::ffi::OsString;
 use std::fmt;
-
-use failure::{err_msg, Error};
+use std::path::Path;
 
 use outparse::BuildReport;
 
+use crate::{missing, unicode};
+
+/// Add what can be found out about the errors of `report` from the
+/// sources of `document`, such as suggested fixes.
+pub fn annotate(report: &mut BuildReport, document: &Path) {
+    unicode::annotate(report);
+    missing::annotate(report, document);
+}
+
 pub type ReportMap = HashMap<OsString, BuildReport>;
 
 #[derive(Debug)]
@@ -1,105 +1,306 @@
-use serde::{Deserialize, Serialize};
 use std::collections::VecDeque;
 use std::path::{Path, PathBuf};
-use std::process::exit;
-use std::iter::Iterator;
-use std::sync::{Arc, atomic::{AtomicBool, Ordering}};
-use std::ops::Deref;
-use std::rc::Rc;
+use std::sync::mpsc::{channel, Receiver, Sender};
+use std::sync::Arc;
 
-use failure::{err_msg, Error as E, bail};
+use failure::{bail, err_msg, Error as E};
 
-
-
-use outparse::{parse_log, BuildReport};
+use outparse::ParseEvent;
 
 use crate::config::Config;
-use crate::engine::get_extension_for_engine;
-use crate::jobs::{Job, JobStatus};
+use crate::events::{EventKind, EventQueue, PassReason, RunnerEvent};
+use crate::jobs::{Job, JobMessage, JobStatus, Prerequisites};
 use crate::report::RunnerReport;
 
-
-#[derive(Debug)]
-pub enum ReportFormat {
-    Human,
-    Json,
+/// Handle that can be used to stop a runner from another thread,
+/// for instance from a Ctrl-C handler.
+#[derive(Clone)]
+pub struct AbortHandle {
+    sender: Sender<JobMessage>,
 }
 
+impl AbortHandle {
+    /// Kill all running jobs and discard the pending ones.
+    pub fn abort(&self) {
+        let _ = self.sender.send(JobMessage::Abort);
+    }
+}
 
 pub struct Runner {
     config: Arc<Config>,
 
-    abort: Arc<AtomicBool>,
+    events: EventQueue,
+
+    sender: Sender<JobMessage>,
+    receiver: Receiver<JobMessage>,
+    next_id: usize,
 
     pending: VecDeque<Job>,
 
     active: VecDeque<Job>,
     completed: Vec<Job>,
-    failed: Vec<Job>,
-
 }
 
 impl Runner {
-
-    pub fn new<P: AsRef<Path>>(
-        config: Arc<Config>, 
-        jobs: &[P]
-    )-> Runner {
-
-        let pending = jobs.iter().map(|p| {
-            Job::new(config.clone(), p.as_ref())
-        }).collect();
-
+    pub fn new<P: AsRef<Path>>(config: Arc<Config>, jobs: &[P]) -> Runner {
+        let (sender, receiver) = channel();
         let active = VecDeque::with_capacity(config.max_jobs);
-        Runner {
+        let mut runner = Runner {
             config,
-            abort: Arc::new(AtomicBool::new(false)),
-            pending,
+            events: EventQueue::new(),
+            sender,
+            receiver,
+            next_id: 0,
+            pending: VecDeque::new(),
             active,
             completed: Vec::new(),
-            failed: Vec::new(),
+        };
+        for path in jobs {
+            runner.queue(path.as_ref());
+        }
+        runner.resolve_dependencies();
+        runner
+    }
 
+    fn all_jobs(&self) -> impl Iterator<Item = &Job> {
+        self.pending
+            .iter()
+            .chain(self.active.iter())
+            .chain(self.completed.iter())
+    }
+
+    /// Work out which of the pending jobs depend on other jobs
+    /// known to this runner.
+    fn resolve_dependencies(&mut self) {
+        let documents: Vec<(PathBuf, PathBuf)> = self
+            .all_jobs()
+            .map(|j| (j.key.clone(), j.output_key.clone()))
+            .collect();
+        let documents: Vec<(&PathBuf, &PathBuf)> = documents.iter().map(|(k, o)| (k, o)).collect();
+        for job in self.pending.iter_mut() {
+            job.resolve_dependencies(&documents);
         }
     }
 
+    fn prerequisites(&self, job: &Job) -> Prerequisites {
+        job.prerequisites(
+            |name| self.pending.iter().chain(self.active.iter()).any(|j| &j.key == name),
+            |name| {
+                let last = self.completed.iter().rev().find(|j| &j.key == name);
+                matches!(last.map(|j| &j.status), Some(JobStatus::Failed))
+            },
+        )
+    }
+
+    fn queue(&mut self, path: &Path) {
+        let job = Job::new(self.next_id, self.config.clone(), path);
+        self.events.push_back(RunnerEvent::new(
+            job.id,
+            EventKind::JobQueued {
+                path: job.path.clone(),
+            },
+        ));
+        self.next_id += 1;
+        self.pending.push_back(job);
+    }
+
     pub fn submit(&mut self, path: &Path) -> Result<(), E> {
         if !path.exists() {
             bail!("The file {} does not exist", path.display())
         }
-        let job = Job::new(self.config.clone(), path);
-        self.pending.push_back(job);
+        // The new result replaces that of any earlier build.
+        self.completed.retain(|j| j.path != path);
+        self.queue(path);
+        self.resolve_dependencies();
         Ok(())
     }
 
-    fn push_next_job(&mut self) {
-        if let Some(mut job) = self.pending.pop_front() {
-            job.spawn().expect("Cannot launch new job");
-            self.active.push_back(job);
+    pub fn abort_handle(&self) -> AbortHandle {
+        AbortHandle {
+            sender: self.sender.clone(),
         }
     }
 
-    pub fn process_till_next_complete(&mut self) -> Option<&Job> {
+    /// Find a job by id, whether it is pending, active or complete.
+    pub fn job(&self, id: usize) -> Option<&Job> {
+        self.all_jobs().find(|j| j.id == id)
+    }
 
-        if self.active.is_empty() && !self.pending.is_empty() {
-            (0..self.active.capacity()).for_each(|_| self.push_next_job());
+    /// Take the first pending job whose prerequisites have been built.
+    ///
+    /// Jobs with a failed prerequisite are marked as failed and moved
+    /// straight to the completed list.
+    fn next_ready_job(&mut self) -> Option<Job> {
+        let mut i = 0;
+        while i < self.pending.len() {
+            match self.prerequisites(&self.pending[i]) {
+                Prerequisites::Ready => return self.pending.remove(i),
+                Prerequisites::Waiting => i += 1,
+                Prerequisites::Failed => {
+                    let mut job = self.pending.remove(i).unwrap();
+                    job.status = JobStatus::Failed;
+                    self.complete(job);
+                }
+            }
         }
+        None
+    }
 
-        while !self.active.is_empty() {
-            if let Some(i) = self.active.iter_mut().position(|j| j.poll()) {
-                let job = self.active.remove(i).unwrap();
-                self.completed.push(job);
+    /// Start pending jobs until all the job slots are filled, or no
+    /// pending job has its prerequisites built yet.
+    ///
+    /// Jobs that cannot be launched are marked as failed and moved
+    /// straight to the completed list.
+    fn push_next_jobs(&mut self) {
+        let max_jobs = self.config.max_jobs.max(1);
+        while self.active.len() < max_jobs {
+            let mut job = match self.next_ready_job() {
+                Some(job) => job,
+                None => break,
+            };
+            if job
+                .spawn(PassReason::Initial, &self.sender, &mut self.events)
+                .is_err()
+            {
+                job.status = JobStatus::Failed;
+                self.complete(job);
+            } else {
+                self.active.push_back(job);
+            }
+        }
+
+        // Nothing is running, so whatever is left depends on itself.
+        if self.active.is_empty() {
+            while let Some(mut job) = self.pending.pop_front() {
+                job.status = JobStatus::Failed;
+                self.complete(job);
+            }
+        }
+    }
+
+    fn complete(&mut self, job: Job) {
+        self.events.push_back(RunnerEvent::new(
+            job.id,
+            EventKind::JobFinished {
+                status: job.status.clone(),
+            },
+        ));
+        let rebuilt = matches!(job.status, JobStatus::Success);
+        let key = job.key.clone();
+        self.completed.push(job);
+
+        if rebuilt {
+            self.requeue_dependants(&key);
+        }
+    }
+
+    /// Queue the documents that were built against an older output
+    /// of the document `key` again.
+    fn requeue_dependants(&mut self, key: &PathBuf) {
+        let queued: Vec<PathBuf> = self
+            .pending
+            .iter()
+            .chain(self.active.iter())
+            .map(|j| j.key.clone())
+            .collect();
+        let (stale, current): (Vec<Job>, Vec<Job>) = self
+            .completed
+            .drain(..)
+            .partition(|j| j.depends_on.contains(key) && !queued.contains(&j.key));
+        self.completed = current;
+
+        if stale.is_empty() {
+            return;
+        }
+        for job in stale {
+            self.queue(&job.path);
+        }
+        self.resolve_dependencies();
+    }
 
-                self.push_next_job();
-                return Some(self.completed.last().unwrap())
+    fn handle_message(&mut self, message: JobMessage) {
+        let (id, finished) = match message {
+            JobMessage::Abort => {
+                self.kill();
+                return;
+            }
+            JobMessage::Progress { id, event } => {
+                let job = match self.active.iter_mut().find(|j| j.id == id) {
+                    Some(job) => job,
+                    None => return,
+                };
+                if let ParseEvent::Message(message) = event {
+                    self.events
+                        .push_back(RunnerEvent::new(id, EventKind::MessageParsed(message)));
+                } else if job.progress.update(&event) {
+                    let progress = job.progress.clone();
+                    self.events
+                        .push_back(RunnerEvent::new(id, EventKind::Progress(progress)));
+                }
+                return;
+            }
+            JobMessage::Finished { id, report } => {
+                let job = match self.active.iter_mut().find(|j| j.id == id) {
+                    Some(job) => job,
+                    None => return,
+                };
+                (id, job.finish(*report, &self.sender, &mut self.events))
             }
+            JobMessage::ToolFinished { id, tool, success } => {
+                let job = match self.active.iter_mut().find(|j| j.id == id) {
+                    Some(job) => job,
+                    None => return,
+                };
+                (id, job.finish_tool(tool, success, &self.sender, &mut self.events))
+            }
+        };
+
+        if finished {
+            let i = self.active.iter().position(|j| j.id == id).unwrap();
+            let job = self.active.remove(i).unwrap();
+            self.complete(job);
         }
+    }
 
+    /// Block until something happens to one of the jobs, and
+    /// return the event.
+    ///
+    /// Pending jobs are started as job slots become free. Returns
+    /// `None` once every job is complete.
+    pub fn next_event(&mut self) -> Option<RunnerEvent> {
+        loop {
+            if let Some(event) = self.events.pop_front() {
+                return Some(event);
+            }
+
+            self.push_next_jobs();
+            if !self.events.is_empty() {
+                continue;
+            }
+            if self.active.is_empty() {
+                return None;
+            }
 
+            match self.receiver.recv() {
+                Ok(message) => self.handle_message(message),
+                Err(_) => return None,
+            }
+        }
+    }
+
+    /// Block until the next job completes, and return it.
+    ///
+    /// Returns `None` once there are no active or pending jobs left.
+    pub fn process_till_next_complete(&mut self) -> Option<&Job> {
+        while let Some(event) = self.next_event() {
+            if let EventKind::JobFinished { .. } = event.kind {
+                return self.completed.iter().find(|j| j.id == event.job);
+            }
+        }
         None
     }
 
     fn kill(&mut self) {
-        self.abort.store(true, Ordering::Release);
         self.active.iter_mut().for_each(|j| j.kill());
         self.pending.clear();
     }
@@ -108,8 +309,8 @@ impl Runner {
         if !self.config.clean_build {
             return Ok(());
         }
-        for job in self.completed.iter_mut() {
-            job.cleanup()?;
+        for job in self.completed.iter() {
+            job.cleanup(self.config.clean_level, false)?;
         }
         Ok(())
     }
@@ -127,17 +328,259 @@ impl Runner {
         }
         Ok(report)
     }
-
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use std::ffi::OsString;
+    use outparse::BuildReport;
+    use crate::events::Tool;
+    use std::path::PathBuf;
 
     fn make_config() -> Arc<Config> {
         Arc::new(Config::default())
     }
 
+    #[test]
+    fn test_parallel_jobs_all_complete() {
+        let config = Arc::new(Config {
+            engine: OsString::from("echo"),
+            max_jobs: 2,
+            ..Config::default()
+        });
+        let paths = ["a.tex", "b.tex", "c.tex"];
+        let mut runner = Runner::new(config, &paths);
+
+        let mut names = Vec::new();
+        while let Some(job) = runner.process_till_next_complete() {
+            names.push(job.jobname.clone());
+        }
+        names.sort();
+
+        assert_eq!(names, vec!["a", "b", "c"]);
+        let report = runner.build_report().unwrap();
+        assert_eq!(report.success, 3);
+        assert_eq!(report.fail, 0);
+    }
+
+    #[test]
+    fn test_event_stream_for_single_job() {
+        let config = Arc::new(Config {
+            engine: OsString::from("echo"),
+            ..Config::default()
+        });
+        let mut runner = Runner::new(config, &["a.tex"]);
+
+        let mut kinds = Vec::new();
+        while let Some(event) = runner.next_event() {
+            assert_eq!(event.job, 0);
+            kinds.push(event.kind);
+        }
+
+        assert_eq!(kinds.len(), 3);
+        assert!(matches!(kinds[0], EventKind::JobQueued { .. }));
+        assert!(matches!(
+            kinds[1],
+            EventKind::PassStarted {
+                pass: 1,
+                reason: PassReason::Initial
+            }
+        ));
+        assert!(matches!(
+            kinds[2],
+            EventKind::JobFinished {
+                status: JobStatus::Success
+            }
+        ));
+    }
+
+    fn finished_order(runner: &mut Runner) -> Vec<(OsString, JobStatus)> {
+        let mut finished = Vec::new();
+        while let Some(event) = runner.next_event() {
+            if let EventKind::JobFinished { status } = event.kind {
+                let job = runner.job(event.job).unwrap();
+                finished.push((job.jobname.clone(), status));
+            }
+        }
+        finished
+    }
+
+    #[test]
+    fn test_prerequisite_built_first() {
+        let config = Arc::new(Config {
+            engine: OsString::from("echo"),
+            max_jobs: 2,
+            dependencies: vec![String::from("main.tex:figures.tex")],
+            ..Config::default()
+        });
+        let mut runner = Runner::new(config, &["main.tex", "figures.tex"]);
+
+        let finished = finished_order(&mut runner);
+
+        assert_eq!(finished.len(), 2);
+        assert_eq!(finished[0].0, "figures");
+        assert_eq!(finished[1].0, "main");
+        assert!(matches!(finished[1].1, JobStatus::Success));
+    }
+
+    #[test]
+    fn test_references_match_directories() {
+        let dir = std::env::temp_dir().join(format!("rustex-runner-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(dir.join("paper")).unwrap();
+        std::fs::create_dir_all(dir.join("data")).unwrap();
+        std::fs::write(
+            dir.join("paper/main.tex"),
+            "\\includegraphics{results}\n\\includegraphics{plot}\n",
+        )
+        .unwrap();
+        let config = Arc::new(Config {
+            engine: OsString::from("echo"),
+            ..Config::default()
+        });
+        let paths = [
+            dir.join("paper/main.tex"),
+            dir.join("data/results.tex"),
+            dir.join("paper/plot.tex"),
+        ];
+        let runner = Runner::new(config, &paths);
+
+        let main = runner.job(0).unwrap();
+        assert_eq!(main.depends_on, vec![runner.job(2).unwrap().key.clone()]);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_dependency_cycle_fails() {
+        let config = Arc::new(Config {
+            engine: OsString::from("echo"),
+            dependencies: vec![String::from("a.tex:b.tex"), String::from("b.tex:a.tex")],
+            ..Config::default()
+        });
+        let mut runner = Runner::new(config, &["a.tex", "b.tex"]);
+
+        let finished = finished_order(&mut runner);
+
+        assert_eq!(finished.len(), 2);
+        assert!(finished
+            .iter()
+            .all(|(_, status)| matches!(status, JobStatus::Failed)));
+    }
+
+    #[test]
+    fn test_abort_during_tool() {
+        let config = Arc::new(Config {
+            engine: OsString::from("echo"),
+            ..Config::default()
+        });
+        let mut runner = Runner::new(config, &["a.tex"]);
+        // As if the first pass were done and BibTeX were running.
+        let job = runner.pending.pop_front().unwrap();
+        runner.active.push_back(job);
+        runner.events.clear();
+
+        runner.handle_message(JobMessage::Abort);
+        runner.handle_message(JobMessage::ToolFinished {
+            id: 0,
+            tool: Tool::Bibtex,
+            success: true,
+        });
+
+        assert!(runner.active.is_empty());
+        assert!(matches!(runner.completed[0].status, JobStatus::Failed));
+        assert!(!runner
+            .events
+            .iter()
+            .any(|e| matches!(e.kind, EventKind::PassStarted { .. })));
+    }
+
+    /// Build `a.tex` with a shell script standing in for the engine,
+    /// which writes `log` to the log file and nothing to its output,
+    /// and return the report of the job.
+    fn build_with_log(name: &str, log: &str) -> BuildReport {
+        let dir = std::env::temp_dir().join(format!("rustex-log-{}-{}", name, std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        let fixture = dir.join("fixture.log");
+        std::fs::write(&fixture, log).unwrap();
+        let script = format!("cat '{}' > '{}'", fixture.display(), dir.join("a.log").display());
+        let config = Arc::new(Config {
+            engine: OsString::from("sh"),
+            flags: vec![OsString::from("-c"), OsString::from(script), OsString::from("sh")],
+            build_directory: Some(dir.clone().into_os_string()),
+            ..Config::default()
+        });
+        let mut runner = Runner::new(config, &["a.tex"]);
+
+        let report = runner.process_till_next_complete().unwrap().report.clone().unwrap();
+        std::fs::remove_dir_all(&dir).unwrap();
+        report
+    }
+
+    #[test]
+    fn test_memory_usage_read_from_log() {
+        let report = build_with_log(
+            "memory",
+            concat!(
+                "Here is how much of TeX's memory you used:\n",
+                " 5 strings out of 478287\n",
+                " 100 string characters out of 5849356\n",
+                " 4000 words of memory out of 5000000\n",
+            ),
+        );
+
+        assert_eq!(report.resources.limits.len(), 3);
+        assert_eq!(report.resources.limits[2].used, 4000);
+    }
+
+    #[test]
+    fn test_missing_characters_read_from_log() {
+        let report = build_with_log(
+            "glyphs",
+            "Missing character: There is no \u{3b1} (U+03B1) in font [lmroman10-regular]:+tlig;!\n",
+        );
+
+        let missing: Vec<&String> = report.fonts.missing_glyphs.values().flatten().collect();
+        assert_eq!(missing, vec!["\u{3b1} (U+03B1)"]);
+    }
+
+    #[test]
+    fn test_badbox_snippet_read_from_log() {
+        let report = build_with_log(
+            "snippet",
+            concat!(
+                "(./a.tex\n",
+                "Overfull \\hbox (15.74872pt too wide) in paragraph at lines 12--14\n",
+                "[]\\T1/lmr/m/n/10 (+20) This is some text that goes on and \n",
+                "on[] \n",
+                "\n",
+                ")\n",
+            ),
+        );
+
+        let overfull = report.messages[0].as_ref().unwrap();
+        assert_eq!(overfull.details["snippet"], "This is some text that goes on and on");
+    }
+
+    #[test]
+    fn test_error_help_read_from_log() {
+        let report = build_with_log(
+            "help",
+            concat!(
+                "! Undefined control sequence.\n",
+                "l.14 \\foo\n",
+                "           \n",
+                "The control sequence at the end of the top line\n",
+                "of your error message was never \\def'ed.\n",
+                "\n",
+            ),
+        );
+
+        assert_eq!(report.errors, 1);
+        assert!(report.messages[0].help().unwrap().ends_with("was never \\def'ed."));
+    }
+
     #[test]
     fn test_build_with_pdflatex() {
         let config = make_config();
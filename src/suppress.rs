@@ -0,0 +1,273 @@
+use std::collections::{HashMap, HashSet};
+use std::ffi::{OsStr, OsString};
+use std::fs;
+use std::path::Path;
+use std::str::FromStr;
+
+use failure::{err_msg, Error};
+use lazy_static::lazy_static;
+use regex::Regex;
+
+use outparse::{BuildReport, Message};
+
+use crate::dependencies::strip_comment;
+
+lazy_static! {
+    /// Line numbers in message text, which change whenever the
+    /// sources are edited.
+    static ref LINE_NUMBERS: Regex = Regex::new(r#"\b(lines?) \d+(?:--\d+)?"#).unwrap();
+}
+
+/// Comment that hides the messages about the line it is on, or
+/// about the line below when it is on a line of its own. Errors are
+/// not hidden.
+const IGNORE_COMMENT: &str = "% rustex-ignore";
+
+/// Categories of messages that can be suppressed. Errors cannot.
+const CATEGORIES: &[&str] = &[
+    "warning",
+    "badbox",
+    "info",
+    "missing-citation",
+    "missing-reference",
+    "multiply-defined",
+];
+
+/// A rule selecting messages to suppress.
+#[derive(Debug, Clone)]
+pub enum Rule {
+    /// Messages from a package or class.
+    Package(String),
+    /// Messages whose text matches a regular expression.
+    Message(Regex),
+    /// Messages about files matching a glob, where `*` matches
+    /// within a directory and `**` across directories.
+    File(String),
+    /// Messages of a category, such as `badbox`.
+    Category(String),
+}
+
+impl FromStr for Rule {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Rule, Error> {
+        let (kind, value) = match s.split_once(':') {
+            Some(rule) => rule,
+            None => return Err(err_msg(format!("Suppression rule has no kind: {}", s))),
+        };
+        match kind {
+            "package" => Ok(Rule::Package(value.to_owned())),
+            "message" => Ok(Rule::Message(Regex::new(value)?)),
+            "file" => Ok(Rule::File(value.to_owned())),
+            "category" if CATEGORIES.contains(&value) => Ok(Rule::Category(value.to_owned())),
+            "category" => Err(err_msg(format!(
+                "Unrecognised category: {} (expected one of {})",
+                value,
+                CATEGORIES.join(", ")
+            ))),
+            _ => Err(err_msg(format!(
+                "Unrecognised suppression rule: {} (expected package, message, file or category)",
+                kind
+            ))),
+        }
+    }
+}
+
+impl Rule {
+    pub fn matches(&self, message: &Message) -> bool {
+        match self {
+            Rule::Package(name) => message.get_component_name() == Some(name),
+            Rule::Message(regex) => regex.is_match(&message.to_str()),
+            Rule::File(glob) => message.file().is_some_and(|f| glob_match(glob, f)),
+            Rule::Category(category) => message.category() == category,
+        }
+    }
+}
+
+fn glob_match(pattern: &str, text: &str) -> bool {
+    if let Some(rest) = pattern.strip_prefix("**") {
+        let rest = rest.strip_prefix('/').unwrap_or(rest);
+        return (0..=text.len())
+            .filter(|&i| text.is_char_boundary(i))
+            .any(|i| glob_match(rest, &text[i..]));
+    }
+    let mut pattern_chars = pattern.chars();
+    match pattern_chars.next() {
+        None => text.is_empty(),
+        Some('*') => {
+            let rest = pattern_chars.as_str();
+            for (i, c) in text.char_indices() {
+                if glob_match(rest, &text[i..]) {
+                    return true;
+                }
+                if c == '/' {
+                    return false;
+                }
+            }
+            glob_match(rest, "")
+        }
+        Some(p) => {
+            let mut text_chars = text.chars();
+            match text_chars.next() {
+                Some(c) if p == '?' && c != '/' || p == c => {
+                    glob_match(pattern_chars.as_str(), text_chars.as_str())
+                }
+                _ => false,
+            }
+        }
+    }
+}
+
+/// A line identifying a message that does not change when lines
+/// are added to or removed from the sources.
+pub fn fingerprint(message: &Message) -> String {
+    let text = message.to_str();
+    let text = LINE_NUMBERS.replace_all(text.trim(), "$1 N");
+    format!("{}\t{}\t{}", message.category(), message.file().unwrap_or(""), text)
+}
+
+/// Messages that are already known, so do not need to be reported
+/// again.
+#[derive(Debug, Clone, Default)]
+pub struct Baseline {
+    fingerprints: HashSet<String>,
+}
+
+impl Baseline {
+    /// Read a baseline written by [`write_baseline`], as an argument
+    /// parser.
+    pub fn read(path: &OsStr) -> Result<Baseline, OsString> {
+        match fs::read_to_string(path) {
+            Ok(contents) => Ok(Baseline {
+                fingerprints: contents.lines().map(|l| l.to_owned()).collect(),
+            }),
+            Err(e) => {
+                let mut message = OsString::from("Could not read baseline ");
+                message.push(path);
+                message.push(format!(": {}", e));
+                Err(message)
+            }
+        }
+    }
+
+    pub fn contains(&self, message: &Message) -> bool {
+        self.fingerprints.contains(&fingerprint(message))
+    }
+}
+
+/// Write the messages of `reports` to `path` as a baseline. Errors
+/// are left out, as they are never suppressed.
+pub fn write_baseline(path: &Path, reports: &[BuildReport]) -> Result<(), Error> {
+    let mut lines: Vec<String> = reports
+        .iter()
+        .flat_map(|r| r.messages.iter().chain(r.suppressed.iter()))
+        .filter(|m| !matches!(m, Message::Error(_)))
+        .map(fingerprint)
+        .collect();
+    lines.sort();
+    lines.dedup();
+    let mut contents = lines.join("\n");
+    contents.push('\n');
+    fs::write(path, contents)
+        .map_err(|e| err_msg(format!("Could not write {}: {}", path.display(), e)))
+}
+
+/// Whether the source line a message is about is marked with
+/// `% rustex-ignore`. Sources are read once and kept in `sources`.
+fn ignored_in_source(message: &Message, sources: &mut HashMap<String, Vec<String>>) -> bool {
+    let (file, line) = match (message.file(), message.line()) {
+        (Some(file), Some(line)) if line > 0 => (file, line),
+        _ => return false,
+    };
+    let lines = sources.entry(file.to_owned()).or_insert_with(|| {
+        fs::read_to_string(file)
+            .map(|s| s.lines().map(|l| l.to_owned()).collect())
+            .unwrap_or_default()
+    });
+    is_marked(lines, line)
+}
+
+/// Whether line number `line` of `lines` has an ignore comment, or
+/// follows a line with nothing else. Escaped `\%` signs do not
+/// start comments.
+fn is_marked(lines: &[String], line: usize) -> bool {
+    let comment = |l: &str| l[strip_comment(l).len()..].starts_with(IGNORE_COMMENT);
+    let marked = lines.get(line - 1).is_some_and(|l| comment(l));
+    let marked_above = line > 1
+        && lines
+            .get(line - 2)
+            .is_some_and(|l| strip_comment(l).trim().is_empty() && comment(l));
+    marked || marked_above
+}
+
+/// Suppress the messages of `report` that match any of `rules`,
+/// appear in `baseline`, or are marked in the sources.
+///
+/// Errors are always kept, so that a build that failed is never
+/// reported as passing.
+pub fn apply(report: &mut BuildReport, rules: &[Rule], baseline: Option<&Baseline>) {
+    let mut sources = HashMap::new();
+    report.suppress(|m| {
+        if let Message::Error(_) = m {
+            return false;
+        }
+        rules.iter().any(|r| r.matches(m))
+            || baseline.is_some_and(|b| b.contains(m))
+            || ignored_in_source(m, &mut sources)
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_glob_match() {
+        assert!(glob_match("chapters/*.tex", "chapters/intro.tex"));
+        assert!(!glob_match("chapters/*.tex", "chapters/old/intro.tex"));
+        assert!(glob_match("**/intro.tex", "chapters/old/intro.tex"));
+        assert!(glob_match("**/intro.tex", "intro.tex"));
+        assert!(glob_match("fig?.tex", "fig1.tex"));
+    }
+
+    #[test]
+    fn test_rules_and_baseline() {
+        let log = "(./main.tex\nPackage hyperref Warning: Draft mode on.\nUnderfull \\hbox (badness 10000) in paragraph at lines 9--10\n\nLaTeX Warning: Citation `knuth' on page 1 undefined on input line 7.\nLaTeX Font Warning: Font shape `OT1/cmr/bx/sc' undefined on input line 4.\n)\n";
+        let mut report = outparse::parse_log(log.as_bytes());
+        let rules: Vec<Rule> = vec!["package:hyperref".parse().unwrap(), "category:badbox".parse().unwrap()];
+
+        let known = outparse::parse_log(
+            "(./main.tex\nLaTeX Font Warning: Font shape `OT1/cmr/bx/sc' undefined on input line 40.\n)\n".as_bytes(),
+        );
+        let baseline = Baseline {
+            fingerprints: known.messages.iter().map(fingerprint).collect(),
+        };
+
+        apply(&mut report, &rules, Some(&baseline));
+
+        assert_eq!(report.suppressed.len(), 3);
+        assert_eq!(report.warnings, 0);
+        assert_eq!(report.badboxes, 0);
+        assert_eq!(report.missing_citations, 1);
+        assert!("category:unknown".parse::<Rule>().is_err());
+        assert!("category:error".parse::<Rule>().is_err());
+    }
+
+    #[test]
+    fn test_ignore_comments() {
+        let lines: Vec<String> = [
+            "A 100\\% rustex-ignore rate",
+            "\\cite{knuth} % rustex-ignore",
+            "  % rustex-ignore",
+            "\\ref{fig:a}",
+            "50\\% rustex-ignore",
+            "\\ref{fig:b}",
+        ]
+        .iter()
+        .map(|l| l.to_string())
+        .collect();
+
+        let marked: Vec<bool> = (1..=lines.len()).map(|n| is_marked(&lines, n)).collect();
+        assert_eq!(marked, vec![false, true, true, true, false, false]);
+    }
+}
@@ -0,0 +1,221 @@
+use std::fs;
+
+use lazy_static::lazy_static;
+use regex::Regex;
+
+use outparse::{BuildReport, Encoding, Message, MessageInfo};
+
+lazy_static! {
+    static ref INVALID_UTF8: Regex = Regex::new(r#"Invalid UTF-8 byte"#).unwrap();
+
+    static ref UNSET_CHARACTER: Regex = Regex::new(
+        r#"Unicode character .*\(U\+([0-9A-Fa-f]{4,6})\)"#
+    ).unwrap();
+}
+
+/// Characters that are often pasted from PDFs or word processors,
+/// with their names and plain LaTeX replacements.
+const KNOWN_CHARACTERS: &[(char, &str, &str)] = &[
+    ('\u{00A0}', "NO-BREAK SPACE", "~"),
+    ('\u{00AD}', "SOFT HYPHEN", "\\-"),
+    ('\u{00B0}', "DEGREE SIGN", "\\textdegree"),
+    ('\u{00D7}', "MULTIPLICATION SIGN", "$\\times$"),
+    ('\u{03B1}', "GREEK SMALL LETTER ALPHA", "$\\alpha$"),
+    ('\u{03B2}', "GREEK SMALL LETTER BETA", "$\\beta$"),
+    ('\u{03B3}', "GREEK SMALL LETTER GAMMA", "$\\gamma$"),
+    ('\u{03B4}', "GREEK SMALL LETTER DELTA", "$\\delta$"),
+    ('\u{03BC}', "GREEK SMALL LETTER MU", "$\\mu$"),
+    ('\u{03C0}', "GREEK SMALL LETTER PI", "$\\pi$"),
+    ('\u{03C3}', "GREEK SMALL LETTER SIGMA", "$\\sigma$"),
+    ('\u{2002}', "EN SPACE", "\\enspace"),
+    ('\u{2009}', "THIN SPACE", "\\,"),
+    ('\u{200B}', "ZERO WIDTH SPACE", ""),
+    ('\u{2010}', "HYPHEN", "-"),
+    ('\u{2011}', "NON-BREAKING HYPHEN", "\\nobreakdash-"),
+    ('\u{2013}', "EN DASH", "--"),
+    ('\u{2014}', "EM DASH", "---"),
+    ('\u{2018}', "LEFT SINGLE QUOTATION MARK", "`"),
+    ('\u{2019}', "RIGHT SINGLE QUOTATION MARK", "'"),
+    ('\u{201C}', "LEFT DOUBLE QUOTATION MARK", "``"),
+    ('\u{201D}', "RIGHT DOUBLE QUOTATION MARK", "''"),
+    ('\u{2022}', "BULLET", "\\textbullet"),
+    ('\u{2026}', "HORIZONTAL ELLIPSIS", "\\dots"),
+    ('\u{2192}', "RIGHTWARDS ARROW", "$\\rightarrow$"),
+    ('\u{2212}', "MINUS SIGN", "$-$"),
+    ('\u{2248}', "ALMOST EQUAL TO", "$\\approx$"),
+    ('\u{2264}', "LESS-THAN OR EQUAL TO", "$\\leq$"),
+    ('\u{2265}', "GREATER-THAN OR EQUAL TO", "$\\geq$"),
+    ('\u{FB00}', "LATIN SMALL LIGATURE FF", "ff"),
+    ('\u{FB01}', "LATIN SMALL LIGATURE FI", "fi"),
+    ('\u{FB02}', "LATIN SMALL LIGATURE FL", "fl"),
+    ('\u{FEFF}', "ZERO WIDTH NO-BREAK SPACE", ""),
+];
+
+/// What was found at the position an encoding error points to.
+#[derive(Debug, PartialEq)]
+struct Finding {
+    byte_offset: usize,
+    codepoint: String,
+    name: Option<&'static str>,
+    suggestion: String,
+}
+
+fn known(c: char) -> Option<&'static (char, &'static str, &'static str)> {
+    KNOWN_CHARACTERS.iter().find(|(k, _, _)| *k == c)
+}
+
+fn suggest_for(c: char) -> String {
+    match known(c) {
+        Some((_, _, "")) => String::from("remove it, it is invisible"),
+        Some((_, _, replacement)) => format!(
+            "replace it with `{}', or map it with \\newunicodechar{{{}}}{{{}}} from the newunicodechar package",
+            replacement, c, replacement
+        ),
+        None => format!(
+            "map it with \\newunicodechar{{{}}}{{...}} from the newunicodechar package, or build with lualatex or xelatex",
+            c
+        ),
+    }
+}
+
+/// Find the first byte of `line` that is not valid UTF-8.
+fn find_invalid_byte(line: &[u8]) -> Option<Finding> {
+    let offset = match std::str::from_utf8(line) {
+        Ok(_) => return None,
+        Err(e) => e.valid_up_to(),
+    };
+    let byte = line[offset];
+    let (decoded, _) = Encoding::Cp1252.decode(&[byte]);
+    Some(Finding {
+        byte_offset: offset,
+        codepoint: format!("0x{:02X}", byte),
+        name: None,
+        suggestion: format!(
+            "byte 0x{:02X} is `{}' in Windows-1252 and Latin-1, so the file was probably saved in a legacy encoding; save it as UTF-8",
+            byte, decoded
+        ),
+    })
+}
+
+/// Find the first occurrence of the character `code` in `line`.
+fn find_character(line: &[u8], code: u32) -> Option<Finding> {
+    let c = std::char::from_u32(code)?;
+    let text = String::from_utf8_lossy(line);
+    let offset = text.find(c)?;
+    Some(Finding {
+        byte_offset: offset,
+        codepoint: format!("U+{:04X}", code),
+        name: known(c).map(|(_, name, _)| *name),
+        suggestion: suggest_for(c),
+    })
+}
+
+/// Read line `line` of `file` as bytes.
+fn source_line(file: &str, line: usize) -> Option<Vec<u8>> {
+    let contents = fs::read(file).ok()?;
+    contents
+        .split(|&b| b == b'\n')
+        .nth(line.checked_sub(1)?)
+        .map(|l| l.to_vec())
+}
+
+fn annotate_error(info: &mut MessageInfo, line: Option<usize>) {
+    let message = match info.details.get("message") {
+        Some(message) => message.clone(),
+        None => return,
+    };
+    let code = UNSET_CHARACTER
+        .captures(&message)
+        .and_then(|m| u32::from_str_radix(&m[1], 16).ok());
+    if code.is_none() && !INVALID_UTF8.is_match(&message) {
+        return;
+    }
+    let source = match (info.details.get("file"), line) {
+        (Some(file), Some(line)) => source_line(file, line),
+        _ => None,
+    };
+    let finding = match (code, source) {
+        (Some(code), Some(source)) => find_character(&source, code),
+        (None, Some(source)) => find_invalid_byte(&source),
+        _ => None,
+    };
+
+    match finding {
+        Some(finding) => {
+            info.details
+                .insert(String::from("byte_offset"), finding.byte_offset.to_string());
+            info.details
+                .insert(String::from("codepoint"), finding.codepoint);
+            if let Some(name) = finding.name {
+                info.details
+                    .insert(String::from("codepoint_name"), name.to_owned());
+            }
+            info.details
+                .insert(String::from("suggestion"), finding.suggestion);
+        }
+        None => {
+            // The source could not be read, but the log still names
+            // the character.
+            if let Some(c) = code.and_then(std::char::from_u32) {
+                info.details
+                    .insert(String::from("codepoint"), format!("U+{:04X}", c as u32));
+                if let Some((_, name, _)) = known(c) {
+                    info.details
+                        .insert(String::from("codepoint_name"), (*name).to_owned());
+                }
+                info.details
+                    .insert(String::from("suggestion"), suggest_for(c));
+            }
+        }
+    }
+}
+
+/// Add the position, code point and a suggested fix to the encoding
+/// errors in `report`, found by reading the source lines they refer
+/// to.
+pub fn annotate(report: &mut BuildReport) {
+    for message in &mut report.messages {
+        let line = message.line();
+        if let Message::Error(ref mut info) = message {
+            annotate_error(info, line);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_find_invalid_byte() {
+        let finding = find_invalid_byte(b"Caf\xe9 au lait").unwrap();
+
+        assert_eq!(finding.byte_offset, 3);
+        assert_eq!(finding.codepoint, "0xE9");
+        assert!(finding.suggestion.contains("`\u{e9}'"));
+        assert_eq!(find_invalid_byte("Café".as_bytes()), None);
+    }
+
+    #[test]
+    fn test_annotate_unset_character() {
+        let dir = std::env::temp_dir().join(format!("rustex-unicode-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        let source = dir.join("main.tex");
+        fs::write(&source, "Intro\nThe \u{fb01}rst value is \u{2248} 3.\n").unwrap();
+        let log = format!(
+            "({}\n! LaTeX Error: Unicode character \u{2248} (U+2248)\n               not set up for use with LaTeX.\n\nl.2 The \u{fb01}rst value is \u{2248}\n                       3.\n\n)\n",
+            source.display()
+        );
+        let mut report = outparse::parse_log(log.as_bytes());
+
+        annotate(&mut report);
+
+        let details = &report.messages[0].as_ref().unwrap().details;
+        assert_eq!(details["byte_offset"], "20");
+        assert_eq!(details["codepoint"], "U+2248");
+        assert_eq!(details["codepoint_name"], "ALMOST EQUAL TO");
+        assert!(details["suggestion"].contains("$\\approx$"));
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
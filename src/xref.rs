@@ -0,0 +1,489 @@
+use std::collections::{BTreeSet, HashSet};
+use std::fmt;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use lazy_static::lazy_static;
+use regex::Regex;
+use serde::Serialize;
+
+use outparse::{BuildReport, DuplicateKind, Message};
+
+use crate::dependencies::{source_files, strip_comment};
+
+lazy_static! {
+    static ref LABEL: Regex = Regex::new(r#"\\label\{([^}]+)\}"#).unwrap();
+
+    static ref REFERENCE: Regex = Regex::new(
+        r#"\\(?:ref|eqref|pageref|autoref|nameref|vref|cref|Cref|cpageref|labelcref)\*?\{([^}]+)\}"#
+    ).unwrap();
+
+    static ref CITATION: Regex = Regex::new(
+        r#"\\(?:no|foot|auto|paren|text|full)?[cC]ite(?:[pt]|author|year|alp)?\*?(?:\[[^\]]*\])*\{([^}]+)\}"#
+    ).unwrap();
+
+    static ref AUX_LABEL: Regex = Regex::new(
+        r#"^\\newlabel\{([^}]+)\}(?:\{\{([^{}]*)\}\{([^{}]*)\})?"#
+    ).unwrap();
+
+    static ref AUX_CITATION: Regex = Regex::new(r#"^\\citation\{([^}]+)\}"#).unwrap();
+
+    static ref AUX_BIBDATA: Regex = Regex::new(r#"^\\bibdata\{([^}]+)\}"#).unwrap();
+
+    static ref AUX_INPUT: Regex = Regex::new(r#"^\\@input\{([^}]+)\}"#).unwrap();
+
+    static ref BIB_ENTRY: Regex = Regex::new(r#"^\s*@(\w+)\s*[{(]\s*([^,\s]+)\s*,"#).unwrap();
+}
+
+/// Whether a key is used as a cross reference or a citation.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub enum KeyKind {
+    Reference,
+    Citation,
+}
+
+/// A place in the sources where a key is used or defined.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Location {
+    pub file: PathBuf,
+    pub line: usize,
+}
+
+impl fmt::Display for Location {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}:{}", self.file.display(), self.line)
+    }
+}
+
+/// A key that LaTeX reported as undefined.
+#[derive(Debug, Clone, Serialize)]
+pub struct UndefinedKey {
+    pub kind: KeyKind,
+    pub label: String,
+    /// Where the key is used in the sources.
+    pub uses: Vec<Location>,
+    /// The closest key that is defined, if any is close enough to
+    /// be a likely misspelling.
+    pub suggestion: Option<String>,
+}
+
+/// A label as written to an `.aux` file.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct AuxDefinition {
+    pub aux: PathBuf,
+    /// The text that references to the label print.
+    pub number: Option<String>,
+    pub page: Option<String>,
+}
+
+impl fmt::Display for AuxDefinition {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.aux.display())?;
+        if let Some(ref number) = self.number {
+            write!(f, " as `{}'", number)?;
+        }
+        if let Some(ref page) = self.page {
+            write!(f, " on page {}", page)?;
+        }
+        Ok(())
+    }
+}
+
+/// A key that LaTeX reported as defined more than once.
+#[derive(Debug, Clone, Serialize)]
+pub struct DuplicateKey {
+    pub kind: DuplicateKind,
+    pub label: String,
+    /// Each definition written to the `.aux` files.
+    pub definitions: Vec<AuxDefinition>,
+    /// Where the key is defined in the sources or bibliographies.
+    pub sources: Vec<Location>,
+}
+
+/// Cross references and citations of a document checked against
+/// its sources, `.aux` files and bibliographies.
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct XrefReport {
+    pub undefined: Vec<UndefinedKey>,
+    pub multiply_defined: Vec<DuplicateKey>,
+    /// Labels defined in the sources that are never referenced.
+    pub unused_labels: Vec<String>,
+    /// Bibliography entries that are never cited.
+    pub unused_entries: Vec<String>,
+}
+
+impl XrefReport {
+    /// Whether the analysis found nothing to report.
+    pub fn is_empty(&self) -> bool {
+        self.undefined.is_empty()
+            && self.multiply_defined.is_empty()
+            && self.unused_labels.is_empty()
+            && self.unused_entries.is_empty()
+    }
+}
+
+impl fmt::Display for XrefReport {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for key in &self.undefined {
+            let kind = match key.kind {
+                KeyKind::Reference => "reference",
+                KeyKind::Citation => "citation",
+            };
+            write!(f, "Undefined {} `{}'", kind, key.label)?;
+            if let Some(ref suggestion) = key.suggestion {
+                write!(f, " (did you mean `{}'?)", suggestion)?;
+            }
+            writeln!(f)?;
+            for location in &key.uses {
+                writeln!(f, "  used at {}", location)?;
+            }
+        }
+        for key in &self.multiply_defined {
+            writeln!(f, "Multiply defined {} `{}'", key.kind, key.label)?;
+            for definition in &key.definitions {
+                writeln!(f, "  written to {}", definition)?;
+            }
+            for location in &key.sources {
+                writeln!(f, "  defined at {}", location)?;
+            }
+        }
+        if !self.unused_labels.is_empty() {
+            writeln!(f, "Labels never referenced: {}", self.unused_labels.join(", "))?;
+        }
+        if !self.unused_entries.is_empty() {
+            writeln!(f, "Bibliography entries never cited: {}", self.unused_entries.join(", "))?;
+        }
+        Ok(())
+    }
+}
+
+/// Keys defined and used by a document.
+#[derive(Debug, Default)]
+struct Keys {
+    /// Labels defined with `\label` in the sources.
+    source_labels: Vec<(String, Location)>,
+    references: Vec<(String, Location)>,
+    citations: Vec<(String, Location)>,
+    /// Labels written to the `.aux` files.
+    aux_labels: Vec<(String, AuxDefinition)>,
+    aux_citations: HashSet<String>,
+    bib_files: Vec<String>,
+}
+
+fn split_keys(keys: &str) -> impl Iterator<Item = String> + '_ {
+    keys.split(',').map(|k| k.trim().to_owned()).filter(|k| !k.is_empty())
+}
+
+impl Keys {
+    fn scan_source(&mut self, file: &Path, source: &str) {
+        for (i, line) in source.lines().enumerate() {
+            let line = strip_comment(line);
+            let location = || Location {
+                file: file.to_owned(),
+                line: i + 1,
+            };
+            for m in LABEL.captures_iter(line) {
+                self.source_labels.push((m[1].trim().to_owned(), location()));
+            }
+            for m in REFERENCE.captures_iter(line) {
+                self.references
+                    .extend(split_keys(&m[1]).map(|k| (k, location())));
+            }
+            for m in CITATION.captures_iter(line) {
+                self.citations
+                    .extend(split_keys(&m[1]).map(|k| (k, location())));
+            }
+        }
+    }
+
+    /// Read an `.aux` file and the files it inputs, as written for
+    /// each `\include`d file.
+    fn read_aux(&mut self, dir: &Path, aux: &Path, seen: &mut HashSet<PathBuf>) {
+        if !seen.insert(aux.to_owned()) {
+            return;
+        }
+        let contents = match fs::read_to_string(aux) {
+            Ok(contents) => contents,
+            Err(_) => return,
+        };
+        for line in contents.lines() {
+            if let Some(m) = AUX_LABEL.captures(line) {
+                let definition = AuxDefinition {
+                    aux: aux.to_owned(),
+                    number: m.get(2).map(|n| n.as_str().to_owned()),
+                    page: m.get(3).map(|p| p.as_str().to_owned()),
+                };
+                self.aux_labels.push((m[1].to_owned(), definition));
+            } else if let Some(m) = AUX_CITATION.captures(line) {
+                self.aux_citations.extend(split_keys(&m[1]));
+            } else if let Some(m) = AUX_BIBDATA.captures(line) {
+                self.bib_files.extend(split_keys(&m[1]));
+            } else if let Some(m) = AUX_INPUT.captures(line) {
+                self.read_aux(dir, &dir.join(&m[1]), seen);
+            }
+        }
+    }
+}
+
+/// Keys of the entries of a `.bib` file, skipping `@string`,
+/// `@preamble` and `@comment`.
+pub fn bib_entries(bib: &str) -> Vec<String> {
+    bib_entry_lines(bib).into_iter().map(|(key, _)| key).collect()
+}
+
+fn bib_entry_lines(bib: &str) -> Vec<(String, usize)> {
+    bib.lines()
+        .enumerate()
+        .filter_map(|(i, line)| BIB_ENTRY.captures(line).map(|m| (i + 1, m)))
+        .filter(|(_, m)| {
+            let kind = m[1].to_lowercase();
+            kind != "string" && kind != "preamble" && kind != "comment"
+        })
+        .map(|(line, m)| (m[2].to_owned(), line))
+        .collect()
+}
+
+fn edit_distance(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut row: Vec<usize> = (0..=b.len()).collect();
+    for (i, ca) in a.chars().enumerate() {
+        let mut previous = row[0];
+        row[0] = i + 1;
+        for (j, &cb) in b.iter().enumerate() {
+            let current = row[j + 1];
+            row[j + 1] = if ca == cb {
+                previous
+            } else {
+                1 + previous.min(current).min(row[j])
+            };
+            previous = current;
+        }
+    }
+    row[b.len()]
+}
+
+/// The candidate closest to `key`, if it is close enough to be a
+/// likely misspelling.
+fn closest<'a, I: IntoIterator<Item = &'a String>>(key: &str, candidates: I) -> Option<String> {
+    let limit = (key.chars().count() / 3).max(2);
+    candidates
+        .into_iter()
+        .filter(|c| c.as_str() != key)
+        .map(|c| (edit_distance(key, c), c))
+        .filter(|(d, _)| *d <= limit)
+        .min_by(|a, b| a.0.cmp(&b.0).then_with(|| a.1.cmp(b.1)))
+        .map(|(_, c)| c.clone())
+}
+
+fn uses_of(key: &str, uses: &[(String, Location)]) -> Vec<Location> {
+    uses.iter()
+        .filter(|(k, _)| k == key)
+        .map(|(_, l)| l.clone())
+        .collect()
+}
+
+/// Check the undefined and multiply defined keys in `report` against
+/// the sources of the document at `path`, and the `.aux` and `.bib`
+/// files written for `jobname` in `dir`.
+pub fn analyse(path: &Path, dir: &Path, jobname: &str, report: &BuildReport) -> XrefReport {
+    let mut keys = Keys::default();
+    for file in source_files(path) {
+        if let Ok(source) = fs::read_to_string(&file) {
+            keys.scan_source(&file, &source);
+        }
+    }
+    keys.read_aux(dir, &dir.join(format!("{}.aux", jobname)), &mut HashSet::new());
+
+    let source_dir = path.parent().unwrap_or_else(|| Path::new(""));
+    let mut entries = Vec::new();
+    for bib in &keys.bib_files {
+        let name = if bib.ends_with(".bib") {
+            bib.clone()
+        } else {
+            format!("{}.bib", bib)
+        };
+        let file = [source_dir.join(&name), dir.join(&name)]
+            .iter()
+            .find(|f| f.is_file())
+            .cloned();
+        if let Some(file) = file {
+            let contents = fs::read_to_string(&file).unwrap_or_default();
+            for (key, line) in bib_entry_lines(&contents) {
+                let location = Location {
+                    file: file.clone(),
+                    line,
+                };
+                entries.push((key, location));
+            }
+        }
+    }
+    let entry_keys: Vec<String> = entries.iter().map(|(k, _)| k.clone()).collect();
+
+    let labels: BTreeSet<String> = keys
+        .aux_labels
+        .iter()
+        .map(|(l, _)| l.clone())
+        .chain(keys.source_labels.iter().map(|(l, _)| l.clone()))
+        .collect();
+
+    let mut xref = XrefReport::default();
+    let mut reported = HashSet::new();
+    for message in &report.messages {
+        let (kind, label) = match message {
+            Message::MissingReference { label, .. } => (KeyKind::Reference, label),
+            Message::MissingCitation { label, .. } => (KeyKind::Citation, label),
+            _ => continue,
+        };
+        if !reported.insert((kind == KeyKind::Citation, label.clone())) {
+            continue;
+        }
+        let (uses, suggestion) = match kind {
+            KeyKind::Reference => (uses_of(label, &keys.references), closest(label, &labels)),
+            KeyKind::Citation => (uses_of(label, &keys.citations), closest(label, &entry_keys)),
+        };
+        xref.undefined.push(UndefinedKey {
+            kind,
+            label: label.clone(),
+            uses,
+            suggestion,
+        });
+    }
+
+    for message in &report.messages {
+        let (label, kind) = match message {
+            Message::MultiplyDefined { label, kind, .. } => (label, *kind),
+            _ => continue,
+        };
+        if xref.multiply_defined.iter().any(|d| d.label == *label && d.kind == kind) {
+            continue;
+        }
+        let (definitions, sources) = match kind {
+            DuplicateKind::Label => (
+                keys.aux_labels
+                    .iter()
+                    .filter(|(l, _)| l == label)
+                    .map(|(_, d)| d.clone())
+                    .collect(),
+                uses_of(label, &keys.source_labels),
+            ),
+            DuplicateKind::BibEntry => (Vec::new(), uses_of(label, &entries)),
+            DuplicateKind::Destination => (Vec::new(), Vec::new()),
+        };
+        xref.multiply_defined.push(DuplicateKey {
+            kind,
+            label: label.clone(),
+            definitions,
+            sources,
+        });
+    }
+
+    let referenced: HashSet<&str> = keys.references.iter().map(|(k, _)| k.as_str()).collect();
+    for (label, _) in &keys.source_labels {
+        if !referenced.contains(label.as_str()) && !xref.unused_labels.contains(label) {
+            xref.unused_labels.push(label.clone());
+        }
+    }
+
+    let cited: HashSet<&str> = keys
+        .citations
+        .iter()
+        .map(|(k, _)| k.as_str())
+        .chain(keys.aux_citations.iter().map(|k| k.as_str()))
+        .collect();
+    if !cited.contains("*") {
+        for key in entry_keys {
+            if !cited.contains(key.as_str()) && !xref.unused_entries.contains(&key) {
+                xref.unused_entries.push(key);
+            }
+        }
+    }
+
+    xref
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_closest_key() {
+        let labels: Vec<String> = vec!["fig:result".into(), "fig:results-table".into(), "sec:intro".into()];
+
+        assert_eq!(closest("fig:reslt", &labels), Some(String::from("fig:result")));
+        assert_eq!(closest("tab:other", &labels), None);
+    }
+
+    #[test]
+    fn test_analyse_document() {
+        let dir = std::env::temp_dir().join(format!("rustex-xref-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(
+            dir.join("main.tex"),
+            "\\section{Intro}\\label{sec:intro}\n\\label{fig:result}\nSee \\cref{fig:reslt,sec:intro}.\n% \\ref{fig:result}\n\\citep[p.~2]{knuht}\n",
+        )
+        .unwrap();
+        fs::write(
+            dir.join("main.aux"),
+            "\\citation{knuht}\n\\bibdata{refs}\n\\newlabel{sec:intro}{{1}{1}}\n\\newlabel{fig:result}{{1}{1}}\n",
+        )
+        .unwrap();
+        fs::write(
+            dir.join("refs.bib"),
+            "@string{tug = \"TeX Users Group\"}\n@book{knuth,\n  title = {The TeXbook}\n}\n@article{lamport, title={LaTeX}}\n",
+        )
+        .unwrap();
+        let log = "LaTeX Warning: Reference `fig:reslt' on page 1 undefined on input line 3.\nLaTeX Warning: Citation `knuht' on page 1 undefined on input line 5.\n";
+        let report = outparse::parse_log(log.as_bytes());
+
+        let xref = analyse(&dir.join("main.tex"), &dir, "main", &report);
+
+        assert_eq!(xref.undefined.len(), 2);
+        assert_eq!(xref.undefined[0].suggestion, Some(String::from("fig:result")));
+        assert_eq!(xref.undefined[0].uses, vec![Location { file: dir.join("main.tex"), line: 3 }]);
+        assert_eq!(xref.undefined[1].kind, KeyKind::Citation);
+        assert_eq!(xref.undefined[1].suggestion, Some(String::from("knuth")));
+        assert_eq!(xref.unused_labels, vec!["fig:result"]);
+        assert_eq!(xref.unused_entries, vec!["knuth", "lamport"]);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_multiply_defined_sites() {
+        let dir = std::env::temp_dir().join(format!("rustex-xref-dup-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(dir.join("main.tex"), "\\label{fig:a}\n\\include{chapter}\n").unwrap();
+        fs::write(dir.join("chapter.tex"), "\\ref{fig:a}\n\\label{fig:a}\n").unwrap();
+        fs::write(
+            dir.join("main.aux"),
+            "\\newlabel{fig:a}{{1}{1}}\n\\@input{chapter.aux}\n",
+        )
+        .unwrap();
+        fs::write(dir.join("chapter.aux"), "\\newlabel{fig:a}{{2.1}{4}}\n").unwrap();
+        let report = outparse::parse_log("LaTeX Warning: Label `fig:a' multiply defined.\n".as_bytes());
+
+        let xref = analyse(&dir.join("main.tex"), &dir, "main", &report);
+
+        let duplicate = &xref.multiply_defined[0];
+        assert_eq!(duplicate.kind, DuplicateKind::Label);
+        assert_eq!(
+            duplicate.definitions,
+            vec![
+                AuxDefinition {
+                    aux: dir.join("main.aux"),
+                    number: Some(String::from("1")),
+                    page: Some(String::from("1")),
+                },
+                AuxDefinition {
+                    aux: dir.join("chapter.aux"),
+                    number: Some(String::from("2.1")),
+                    page: Some(String::from("4")),
+                },
+            ]
+        );
+        assert_eq!(duplicate.sources.len(), 2);
+        assert!(xref.unused_labels.is_empty());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}